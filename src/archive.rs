@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use flate2::{write::GzEncoder, Compression};
+use tar::{EntryType, Header};
+
+/// The modification time used for every entry so archives are reproducible
+/// (2000-01-01T00:00:00Z).
+const MTIME: u64 = 946_684_800;
+
+/// Bundle the contents of a directory into a `*.tar.gz` archive.
+///
+/// The archive is deterministic. Entries are sorted by path and every entry
+/// gets the same modification time, owner, and permissions, so packaging the
+/// same files will always produce the same bytes.
+#[tracing::instrument(skip_all)]
+pub(crate) fn create_archive(dir: &Path) -> Result<Vec<u8>, Error> {
+    tracing::debug!(dir = %dir.display(), "Creating the package archive");
+
+    let mut paths = Vec::new();
+    find_entries(dir, Path::new(""), &mut paths)?;
+
+    let encoder = GzEncoder::new(Vec::new(), Compression::best());
+    let mut builder = tar::Builder::new(encoder);
+
+    for path in &paths {
+        let full_path = dir.join(path);
+
+        let mut header = Header::new_gnu();
+        header.set_mtime(MTIME);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        if full_path.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, path, std::io::empty())
+        } else {
+            let contents = std::fs::read(&full_path)
+                .with_context(|| format!("Unable to read \"{}\"", full_path.display()))?;
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(contents.len() as u64);
+            builder.append_data(&mut header, path, contents.as_slice())
+        }
+        .with_context(|| format!("Unable to add \"{}\" to the archive", path.display()))?;
+    }
+
+    let archive = builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .context("Unable to finish writing the archive")?;
+
+    tracing::debug!(
+        entries = paths.len(),
+        bytes = archive.len(),
+        "Created the package archive"
+    );
+
+    Ok(archive)
+}
+
+/// Recursively find every file and directory under `base.join(relative)`,
+/// returning their paths relative to `base` in sorted order.
+fn find_entries(base: &Path, relative: &Path, paths: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let dir = base.join(relative);
+
+    let mut names = std::fs::read_dir(&dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.file_name()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .with_context(|| format!("Unable to read the \"{}\" directory", dir.display()))?;
+    names.sort();
+
+    for name in names {
+        let path = relative.join(name);
+        let is_dir = base.join(&path).is_dir();
+
+        paths.push(path.clone());
+
+        if is_dir {
+            find_entries(base, &path, paths)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn populate(dir: &Path) {
+        std::fs::create_dir_all(dir.join("wai")).unwrap();
+        std::fs::write(dir.join("wapm.toml"), "[package]").unwrap();
+        std::fs::write(dir.join("README.md"), "# Hello").unwrap();
+        std::fs::write(dir.join("wai").join("exports.wai"), "add: func()").unwrap();
+    }
+
+    #[test]
+    fn archives_are_reproducible() {
+        let first = tempfile::tempdir().unwrap();
+        populate(first.path());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let second = tempfile::tempdir().unwrap();
+        populate(second.path());
+
+        let first = create_archive(first.path()).unwrap();
+        let second = create_archive(second.path()).unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn entries_are_sorted_and_normalized() {
+        let dir = tempfile::tempdir().unwrap();
+        populate(dir.path());
+
+        let archive = create_archive(dir.path()).unwrap();
+
+        let mut tarball = Vec::new();
+        GzDecoder::new(archive.as_slice())
+            .read_to_end(&mut tarball)
+            .unwrap();
+        let mut archive = tar::Archive::new(tarball.as_slice());
+        let entries: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    header.mode().unwrap(),
+                    header.mtime().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("README.md".to_string(), 0o644, MTIME),
+                ("wai".to_string(), 0o755, MTIME),
+                ("wai/exports.wai".to_string(), 0o644, MTIME),
+                ("wapm.toml".to_string(), 0o644, MTIME),
+            ]
+        );
+    }
+}
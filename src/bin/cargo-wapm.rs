@@ -1,6 +1,6 @@
 use anyhow::Error;
-use cargo_wapm::Publish;
-use clap::Parser;
+use cargo_wapm::{Build, Check, Init, Inspect, Package, Publish};
+use clap::{Parser, Subcommand};
 use tracing_subscriber::EnvFilter;
 
 fn main() -> Result<(), Error> {
@@ -16,13 +16,42 @@ fn main() -> Result<(), Error> {
     let args = Cargo::parse();
     tracing::debug!(?args, "Started");
 
-    match args {
-        Cargo::Wapm(p) => p.execute(),
+    let Cargo::Wapm(Wapm { cmd, publish }) = args;
+
+    match cmd {
+        // Plain "cargo wapm" publishes, for backwards compatibility
+        None => publish.execute(),
+        Some(Cmd::Build(b)) => b.execute(),
+        Some(Cmd::Check(c)) => c.execute(),
+        Some(Cmd::Package(p)) => p.execute(),
+        Some(Cmd::Publish(p)) => p.execute(),
+        Some(Cmd::Inspect(i)) => i.execute(),
+        Some(Cmd::Init(i)) => i.execute(),
     }
 }
 
 #[derive(Debug, Parser)]
 #[clap(name = "cargo", bin_name = "cargo", version, author)]
 enum Cargo {
-    Wapm(Publish),
+    Wapm(Wapm),
+}
+
+/// Publish a crate to the WebAssembly Package Manager.
+#[derive(Debug, Parser)]
+#[clap(author, version, about, args_conflicts_with_subcommands = true)]
+struct Wapm {
+    #[clap(subcommand)]
+    cmd: Option<Cmd>,
+    #[clap(flatten)]
+    publish: Publish,
+}
+
+#[derive(Debug, Subcommand)]
+enum Cmd {
+    Build(Build),
+    Check(Check),
+    Package(Package),
+    Publish(Publish),
+    Inspect(Inspect),
+    Init(Init),
 }
@@ -0,0 +1,36 @@
+use anyhow::{Context, Error};
+use clap::Parser;
+
+use crate::publish::BuildOptions;
+
+/// Compile a crate to WebAssembly (running `wasm-opt` and stripping custom
+/// sections if configured) without packaging it.
+#[derive(Debug, Parser)]
+#[clap(author)]
+pub struct Build {
+    #[clap(flatten)]
+    pub build: BuildOptions,
+}
+
+impl Build {
+    /// Run the [`Build`] command.
+    pub fn execute(self) -> Result<(), Error> {
+        let metadata = self.build.metadata()?;
+        let packages = self.build.packages(&metadata)?;
+
+        for pkg in packages {
+            let _span = tracing::info_span!("build", pkg = pkg.name.as_str()).entered();
+
+            let (manifest, targets) = crate::publish::prepare(pkg, &metadata, &self.build)?;
+            let wasm_paths =
+                crate::publish::build_modules(pkg, &metadata, &manifest, &targets, &self.build)
+                    .with_context(|| format!("Unable to compile \"{}\"", pkg.name))?;
+
+            for path in wasm_paths {
+                tracing::info!(path = %path.display(), "Compiled");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Error};
+use cargo_metadata::{semver::Version, Metadata, Package};
+use clap::Parser;
+
+use crate::{metadata::Features, publish::BuildOptions, MetadataTable};
+
+/// Look for problems that would stop a crate from being published, without
+/// compiling anything.
+#[derive(Debug, Parser)]
+#[clap(author)]
+pub struct Check {
+    /// Path to Cargo.toml
+    #[clap(long, env)]
+    pub manifest_path: Option<PathBuf>,
+    /// Check every crate in this workspace
+    #[clap(short, long, env)]
+    pub workspace: bool,
+    /// Packages to ignore.
+    #[clap(long)]
+    pub exclude: Vec<String>,
+    /// A comma-delimited list of features to enable.
+    #[clap(long)]
+    pub features: Option<Features>,
+    /// Use this namespace instead of the one in Cargo.toml.
+    #[clap(long, env = "WAPM_NAMESPACE")]
+    pub namespace: Option<String>,
+    /// Use this package name instead of the one in Cargo.toml (only when
+    /// checking a single crate).
+    #[clap(long)]
+    pub package_name: Option<String>,
+    /// Check with this version instead of the one in Cargo.toml (only when
+    /// checking a single crate).
+    #[clap(long, value_name = "VERSION")]
+    pub package_version: Option<Version>,
+}
+
+impl Check {
+    /// Run the [`Check`] command.
+    pub fn execute(self) -> Result<(), Error> {
+        let build = self.build_options();
+        let metadata = build.metadata()?;
+        let packages = build.packages(&metadata)?;
+
+        let mut problems = 0;
+
+        for pkg in packages {
+            let _span = tracing::info_span!("check", pkg = pkg.name.as_str()).entered();
+
+            let path = &pkg.manifest_path;
+            let cargo_toml = std::fs::read_to_string(path)
+                .with_context(|| format!("Unable to read \"{}\"", path))?;
+
+            for diagnostic in check_package(pkg, &metadata, &build) {
+                let level = if diagnostic.warning {
+                    "warning"
+                } else {
+                    "error"
+                };
+                match find_line(&cargo_toml, diagnostic.key) {
+                    Some(line) => {
+                        eprintln!("{}: {}\n  --> {}:{}", level, diagnostic.message, path, line)
+                    }
+                    None => eprintln!("{}: {}\n  --> {}", level, diagnostic.message, path),
+                }
+                if !diagnostic.warning {
+                    problems += 1;
+                }
+            }
+        }
+
+        anyhow::ensure!(problems == 0, "Found {} problem(s)", problems);
+        tracing::info!("No problems found");
+
+        Ok(())
+    }
+
+    /// The [`BuildOptions`] a check should use. Nothing gets compiled, so
+    /// only the options for selecting packages and overriding their metadata
+    /// are needed.
+    fn build_options(&self) -> BuildOptions {
+        BuildOptions {
+            manifest_path: self.manifest_path.clone(),
+            workspace: self.workspace,
+            exclude: self.exclude.clone(),
+            features: self.features.clone(),
+            namespace: self.namespace.clone(),
+            package_name: self.package_name.clone(),
+            package_version: self.package_version.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A problem with a package, and the key in its `Cargo.toml` that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Diagnostic {
+    key: &'static str,
+    message: String,
+    /// Warnings are reported, but don't stop the package from being
+    /// published.
+    warning: bool,
+}
+
+impl Diagnostic {
+    fn new(key: &'static str, error: Error) -> Self {
+        Diagnostic {
+            key,
+            message: format!("{:#}", error),
+            warning: false,
+        }
+    }
+
+    fn warning(key: &'static str, message: String) -> Self {
+        Diagnostic {
+            key,
+            message,
+            warning: true,
+        }
+    }
+}
+
+/// Run every check that would normally happen while publishing, collecting
+/// all of the problems instead of stopping at the first one.
+fn check_package(pkg: &Package, metadata: &Metadata, options: &BuildOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let base_dir = pkg.manifest_path.parent().unwrap().as_std_path();
+
+    if let Err(e) = crate::publish::validate_description(pkg) {
+        diagnostics.push(Diagnostic::new("package.description", e));
+    }
+
+    let files = [
+        ("package.readme", pkg.readme.as_ref()),
+        ("package.license-file", pkg.license_file.as_ref()),
+    ];
+    for (key, path) in files {
+        if let Some(path) = path {
+            let full_path = base_dir.join(path);
+            if !full_path.is_file() {
+                let e = anyhow::anyhow!("\"{}\" doesn't exist", full_path.display());
+                diagnostics.push(Diagnostic::new(key, e));
+            }
+        }
+    }
+
+    let mut wapm = match MetadataTable::for_package(pkg, metadata) {
+        Ok(MetadataTable { wapm }) => wapm,
+        Err(e) => {
+            let e =
+                Error::from(e).context("Unable to deserialize the [package.metadata.wapm] table");
+            diagnostics.push(Diagnostic::new("package.metadata.wapm", e));
+            return diagnostics;
+        }
+    };
+    options.apply_overrides(&mut wapm);
+
+    for (key, warning) in crate::publish::name_warnings(pkg, &wapm) {
+        diagnostics.push(Diagnostic::warning(key, warning));
+    }
+
+    if let Some(runtime_dependencies) = &wapm.runtime_dependencies {
+        if let Err(e) =
+            crate::publish::workspace_dependencies(metadata, pkg, runtime_dependencies, options)
+        {
+            diagnostics.push(Diagnostic::new(
+                "package.metadata.wapm.runtime-dependencies",
+                e,
+            ));
+        }
+    }
+
+    if let Some(dependencies) = &wapm.dependencies {
+        if let Err(e) = crate::publish::validate_dependencies(dependencies) {
+            diagnostics.push(Diagnostic::new("package.metadata.wapm.dependencies", e));
+        }
+    }
+
+    if let Some(bindings) = &wapm.bindings {
+        match crate::publish::bindings_files(bindings, base_dir) {
+            Ok(files) => {
+                for (path, _) in files.iter().filter(|(path, _)| !path.is_file()) {
+                    let e = anyhow::anyhow!("\"{}\" doesn't exist", path.display());
+                    diagnostics.push(Diagnostic::new("package.metadata.wapm.bindings", e));
+                }
+            }
+            Err(e) => diagnostics.push(Diagnostic::new("package.metadata.wapm.bindings", e)),
+        }
+    }
+
+    if let Some(target) = wapm
+        .target
+        .as_deref()
+        .filter(|t| crate::publish::is_custom_target(t))
+    {
+        let full_path = base_dir.join(target);
+        if !full_path.is_file() {
+            let e = anyhow::anyhow!("\"{}\" doesn't exist", full_path.display());
+            diagnostics.push(Diagnostic::new("package.metadata.wapm.target", e));
+        }
+    }
+
+    for host_path in wapm.fs.iter().flat_map(|fs| fs.values()) {
+        if let Err(e) = crate::publish::validate_fs_path(host_path, base_dir) {
+            diagnostics.push(Diagnostic::new("package.metadata.wapm.fs", e));
+        }
+    }
+
+    let targets = match crate::publish::select_targets(pkg, &wapm, options) {
+        Ok(targets) => targets,
+        Err(e) => {
+            let key = if wapm.bins.is_none() && wapm.lib.is_none() {
+                "lib.crate-type"
+            } else {
+                "package.metadata.wapm"
+            };
+            diagnostics.push(Diagnostic::new(key, e));
+            return diagnostics;
+        }
+    };
+
+    // We've already checked the dependencies
+    wapm.dependencies = None;
+
+    if let Err(e) = crate::publish::generate_manifest(pkg, wapm, &targets) {
+        diagnostics.push(Diagnostic::new("package.metadata.wapm", e));
+    }
+
+    diagnostics
+}
+
+/// Find the (1-based) line a key is defined on, falling back to its parent
+/// table when the key isn't set explicitly.
+fn find_line(cargo_toml: &str, key: &str) -> Option<usize> {
+    let mut current_table = String::new();
+
+    for (i, line) in cargo_toml.lines().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with('#') {
+            continue;
+        } else if line.starts_with('[') {
+            // Ignore any trailing comment (e.g. "[package] # ...")
+            let header = line.split_once('#').map_or(line, |(header, _)| header);
+            current_table = header
+                .trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split_whitespace()
+                .collect();
+            if current_table == key {
+                return Some(i + 1);
+            }
+        } else if let Some((name, _)) = line.split_once('=') {
+            let name = name.trim().trim_matches('"');
+            if !current_table.is_empty() && format!("{}.{}", current_table, name) == key {
+                return Some(i + 1);
+            }
+        }
+    }
+
+    let (parent, _) = key.rsplit_once('.')?;
+    find_line(cargo_toml, parent)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::fixtures::{package_json, target};
+
+    fn package(description: Option<&str>, wapm: serde_json::Value) -> Package {
+        let mut pkg = package_json("my-tool", vec![target("my-tool", "bin")], wapm, Vec::new());
+        pkg["description"] = json!(description);
+        pkg["readme"] = json!("MISSING.md");
+        serde_json::from_value(pkg).unwrap()
+    }
+
+    fn workspace(pkg: &Package) -> Metadata {
+        crate::fixtures::workspace(vec![serde_json::to_value(pkg).unwrap()])
+    }
+
+    fn keys(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.key).collect()
+    }
+
+    #[test]
+    fn report_every_problem() {
+        let pkg = package(
+            None,
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "dependencies": { "python": "^3.12" },
+                "commands": { "other": {} },
+                "fs": { "/data": "../data" },
+            }),
+        );
+        let metadata = workspace(&pkg);
+
+        let diagnostics = check_package(&pkg, &metadata, &BuildOptions::default());
+
+        assert_eq!(
+            keys(&diagnostics),
+            vec![
+                "package.description",
+                "package.readme",
+                "package.metadata.wapm.dependencies",
+                "package.metadata.wapm.fs",
+                "package.metadata.wapm",
+            ]
+        );
+        assert!(diagnostics[4].message.contains("\"other\" module"));
+    }
+
+    #[test]
+    fn stop_when_the_metadata_table_is_invalid() {
+        let pkg = package(Some("A dummy package."), json!({ "abi": "wasi" }));
+        let metadata = workspace(&pkg);
+
+        let diagnostics = check_package(&pkg, &metadata, &BuildOptions::default());
+
+        assert_eq!(
+            keys(&diagnostics),
+            vec!["package.readme", "package.metadata.wapm"]
+        );
+        assert!(diagnostics[1].message.contains("namespace"));
+    }
+
+    #[test]
+    fn apply_command_line_overrides() {
+        let mut pkg = package(
+            Some("A dummy package."),
+            json!({ "abi": "wasi", "namespace": "wasmer", "package": "my.tool" }),
+        );
+        pkg.readme = None;
+        let metadata = workspace(&pkg);
+        let options = BuildOptions {
+            package_name: Some("my-tool".to_string()),
+            ..Default::default()
+        };
+
+        let without_overrides = check_package(&pkg, &metadata, &BuildOptions::default());
+        let with_overrides = check_package(&pkg, &metadata, &options);
+
+        assert_eq!(
+            keys(&without_overrides),
+            vec!["package.metadata.wapm.package"]
+        );
+        assert!(without_overrides[0].warning);
+        assert!(with_overrides.is_empty(), "{:?}", with_overrides);
+    }
+
+    #[test]
+    fn locate_keys_in_cargo_toml() {
+        let cargo_toml = r#"[package]
+name = "my-tool"
+# description = "commented out"
+description = "A dummy package."
+
+[lib]
+crate-type = ["rlib"]
+
+[package.metadata.wapm] # publishing settings
+namespace = "wasmer"
+abi = "wasi"
+"#;
+
+        assert_eq!(find_line(cargo_toml, "package.description"), Some(4));
+        assert_eq!(find_line(cargo_toml, "lib.crate-type"), Some(7));
+        assert_eq!(find_line(cargo_toml, "package.metadata.wapm"), Some(9));
+        assert_eq!(find_line(cargo_toml, "package.metadata.wapm.fs"), Some(9));
+        assert_eq!(find_line(cargo_toml, "package.metadata.wapm.abi"), Some(11));
+        assert_eq!(find_line(cargo_toml, "package.readme"), Some(1));
+        assert_eq!(find_line(cargo_toml, "dependencies.anyhow"), None);
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use anyhow::{Context, Error};
+
+/// Serialize the package laid out in `dir` as a WebC file.
+#[tracing::instrument(skip_all)]
+pub(crate) fn create_webc(dir: &Path) -> Result<Vec<u8>, Error> {
+    let manifest_path = dir.join("wapm.toml");
+    tracing::debug!(manifest = %manifest_path.display(), "Creating a WebC file");
+
+    let package = webc::wasmer_package::Package::from_manifest(&manifest_path)
+        .with_context(|| format!("Unable to load the package at \"{}\"", dir.display()))?;
+    let webc = package
+        .serialize()
+        .context("Unable to serialize the package as WebC")?;
+
+    tracing::debug!(bytes = webc.len(), "Created the WebC file");
+
+    Ok(webc.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use webc::Container;
+
+    use super::*;
+
+    /// The smallest possible WebAssembly module.
+    const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn bundle_modules_commands_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("wapm.toml"),
+            r#"
+            [package]
+            name = "wasmer/my-tool"
+            version = "1.2.3"
+            description = "A dummy package."
+            readme = "README.md"
+
+            [[module]]
+            name = "my-tool"
+            source = "my-tool.wasm"
+            abi = "wasi"
+
+            [[command]]
+            name = "my-tool"
+            module = "my-tool"
+
+            [fs]
+            "/data" = "data"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README.md"), "# My Tool").unwrap();
+        std::fs::write(dir.path().join("my-tool.wasm"), EMPTY_MODULE).unwrap();
+        std::fs::create_dir(dir.path().join("data")).unwrap();
+        std::fs::write(dir.path().join("data").join("config.json"), "{}").unwrap();
+
+        let webc = create_webc(dir.path()).unwrap();
+
+        let container = Container::from_bytes(webc).unwrap();
+        assert!(container.manifest().commands.contains_key("my-tool"));
+        assert_eq!(
+            container.get_atom("my-tool").unwrap().as_ref(),
+            EMPTY_MODULE
+        );
+        let assets = container.get_volume("atom").unwrap();
+        assert_eq!(
+            assets.read_file("/data/config.json").unwrap().as_ref(),
+            b"{}"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Helpers for creating the `cargo metadata` types used in tests.
+
+use cargo_metadata::Metadata;
+use serde_json::{json, Value};
+
+/// A `bin`, `cdylib`, or `lib` target.
+pub(crate) fn target(name: &str, kind: &str) -> Value {
+    json!({
+        "name": name,
+        "kind": [kind],
+        "crate_types": [kind],
+        "src_path": format!("/path/to/{}/src/{}.rs", name, name),
+    })
+}
+
+/// A path dependency on the `name` package from [`package_json()`]. The `kind`
+/// is either `"normal"`, `"dev"`, or `"build"`.
+pub(crate) fn dependency(name: &str, kind: &str) -> Value {
+    json!({
+        "name": name,
+        "req": "^1.0.0",
+        "kind": if kind == "normal" { Value::Null } else { json!(kind) },
+        "optional": false,
+        "uses_default_features": true,
+        "features": [],
+        "target": null,
+        "path": format!("/path/to/{}", name),
+    })
+}
+
+/// A package living in `/path/to/<name>`, with a `[package.metadata.wapm]`
+/// table and normal dependencies on the other packages in `dependencies`.
+pub(crate) fn package_json(
+    name: &str,
+    targets: Vec<Value>,
+    wapm: Value,
+    dependencies: Vec<&str>,
+) -> Value {
+    let dependencies: Vec<_> = dependencies
+        .into_iter()
+        .map(|dep| dependency(dep, "normal"))
+        .collect();
+
+    json!({
+        "name": name,
+        "version": "1.2.3",
+        "id": format!("{} 1.2.3 (path+file:///path/to/{})", name, name),
+        "description": "A dummy package.",
+        "dependencies": dependencies,
+        "targets": targets,
+        "features": {},
+        "manifest_path": format!("/path/to/{}/Cargo.toml", name),
+        "metadata": { "wapm": wapm },
+    })
+}
+
+/// A workspace containing all of `packages`.
+pub(crate) fn workspace(packages: Vec<Value>) -> Metadata {
+    let members: Vec<_> = packages.iter().map(|p| p["id"].clone()).collect();
+
+    serde_json::from_value(json!({
+        "packages": packages,
+        "workspace_members": members,
+        "resolve": null,
+        "workspace_root": "/path/to",
+        "target_directory": "/path/to/target",
+        "version": 1,
+    }))
+    .unwrap()
+}
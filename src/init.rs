@@ -0,0 +1,260 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Error};
+use cargo_metadata::Target;
+use clap::Parser;
+use toml_edit::{Array, Document, Item, Table};
+
+/// Add a `[package.metadata.wapm]` table to a crate's Cargo.toml.
+#[derive(Debug, Parser)]
+#[clap(author)]
+pub struct Init {
+    /// Path to Cargo.toml
+    #[clap(long, env)]
+    pub manifest_path: Option<PathBuf>,
+    /// The namespace the package will be published under.
+    #[clap(long)]
+    pub namespace: String,
+    /// The ABI to compile for. Defaults to "wasi" for crates with binaries
+    /// and "none" otherwise.
+    #[clap(long, value_parser = ["none", "wasi", "emscripten"])]
+    pub abi: Option<String>,
+}
+
+impl Init {
+    /// Run the [`Init`] command.
+    pub fn execute(self) -> Result<(), Error> {
+        let metadata = crate::metadata::parse_cargo_toml(
+            self.manifest_path.as_deref(),
+            false,
+            None,
+            false,
+            &[],
+        )
+        .context("Unable to parse the workspace's metadata")?;
+        // Note: cargo treats the crate from --manifest-path (or the current
+        // directory) as the root package
+        let pkg = metadata.root_package().context(
+            "Unable to determine which crate to initialize. Use --manifest-path to pick one",
+        )?;
+
+        let _span = tracing::info_span!("init", pkg = pkg.name.as_str()).entered();
+
+        let has_binaries = pkg.targets.iter().any(crate::publish::is_binary);
+        let abi = match &self.abi {
+            Some(abi) => abi.as_str(),
+            None if has_binaries => "wasi",
+            None => "none",
+        };
+        // Binaries get packaged on their own, so we only need a "cdylib" when
+        // the library is the only thing being published
+        let add_cdylib = !has_binaries
+            && pkg
+                .targets
+                .iter()
+                .any(|t| is_library(t) && !crate::publish::is_webassembly_library(t));
+
+        let path = &pkg.manifest_path;
+        let cargo_toml = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read \"{}\"", path))?;
+        // Only set the package name when the crate's name isn't usable
+        let package = crate::metadata::name_warning("package name", &pkg.name)
+            .and_then(|_| crate::metadata::suggest_name(&pkg.name));
+        let updated = add_wapm_metadata(
+            &cargo_toml,
+            &self.namespace,
+            package.as_deref(),
+            abi,
+            add_cdylib,
+        )
+        .with_context(|| format!("Unable to update \"{}\"", path))?;
+        std::fs::write(path, updated)
+            .with_context(|| format!("Unable to write to \"{}\"", path))?;
+
+        tracing::info!(%path, abi, "Added a [package.metadata.wapm] table");
+
+        if pkg.description.is_none() {
+            tracing::warn!("The package needs a description before it can be published");
+        }
+
+        Ok(())
+    }
+}
+
+fn is_library(target: &Target) -> bool {
+    target.kind.iter().any(|k| {
+        matches!(
+            k.as_str(),
+            "lib" | "rlib" | "dylib" | "staticlib" | "cdylib"
+        )
+    })
+}
+
+/// Add a `[package.metadata.wapm]` table (and optionally the `cdylib` crate
+/// type) to a `Cargo.toml` file, leaving the rest of its formatting intact.
+///
+/// The `package_name` is only needed when the crate's name can't be used.
+fn add_wapm_metadata(
+    cargo_toml: &str,
+    namespace: &str,
+    package_name: Option<&str>,
+    abi: &str,
+    add_cdylib: bool,
+) -> Result<String, Error> {
+    let mut doc: Document = cargo_toml.parse().context("Unable to parse the file")?;
+
+    let package = doc
+        .get_mut("package")
+        .and_then(Item::as_table_mut)
+        .context("There is no [package] table")?;
+    let metadata = package
+        .entry("metadata")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_mut()
+        .context("The \"package.metadata\" key should be a table")?;
+    anyhow::ensure!(
+        !metadata.contains_key("wapm"),
+        "The crate already has a [package.metadata.wapm] table"
+    );
+
+    let mut wapm = Table::new();
+    wapm.insert("namespace", toml_edit::value(namespace));
+    if let Some(package_name) = package_name {
+        wapm.insert("package", toml_edit::value(package_name));
+    }
+    wapm.insert("abi", toml_edit::value(abi));
+    metadata.insert("wapm", Item::Table(wapm));
+
+    if add_cdylib {
+        let lib = doc
+            .entry("lib")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .context("The \"lib\" key should be a table")?;
+
+        match lib.get_mut("crate-type") {
+            Some(item) => {
+                let crate_types = item
+                    .as_array_mut()
+                    .context("The \"lib.crate-type\" key should be an array")?;
+                if !crate_types.iter().any(|ty| ty.as_str() == Some("cdylib")) {
+                    crate_types.push("cdylib");
+                }
+            }
+            None => {
+                let crate_types: Array = ["cdylib", "rlib"].into_iter().collect();
+                lib.insert("crate-type", toml_edit::value(crate_types));
+            }
+        }
+    }
+
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_metadata_and_preserve_formatting() {
+        let cargo_toml = r#"# My crate
+[package]
+name = "hello-world"   # the name
+version = "0.1.0"
+
+[dependencies]
+anyhow = "1"
+"#;
+
+        let got = add_wapm_metadata(cargo_toml, "wasmer", None, "none", true).unwrap();
+
+        assert_eq!(
+            got,
+            r#"# My crate
+[package]
+name = "hello-world"   # the name
+version = "0.1.0"
+
+[package.metadata.wapm]
+namespace = "wasmer"
+abi = "none"
+
+[dependencies]
+anyhow = "1"
+
+[lib]
+crate-type = ["cdylib", "rlib"]
+"#
+        );
+    }
+
+    #[test]
+    fn extend_existing_crate_types() {
+        let cargo_toml = r#"[package]
+name = "hello-world"
+
+[package.metadata.docs.rs]
+all-features = true
+
+[lib]
+crate-type = ["rlib"]
+"#;
+
+        let got = add_wapm_metadata(cargo_toml, "wasmer", None, "wasi", true).unwrap();
+
+        assert_eq!(
+            got,
+            r#"[package]
+name = "hello-world"
+
+[package.metadata.docs.rs]
+all-features = true
+
+[package.metadata.wapm]
+namespace = "wasmer"
+abi = "wasi"
+
+[lib]
+crate-type = ["rlib", "cdylib"]
+"#
+        );
+    }
+
+    #[test]
+    fn existing_wapm_tables_are_left_alone() {
+        let cargo_toml = r#"[package]
+name = "hello-world"
+
+[package.metadata.wapm]
+namespace = "wasmer"
+abi = "none"
+"#;
+
+        assert!(add_wapm_metadata(cargo_toml, "someone-else", None, "wasi", false).is_err());
+    }
+
+    #[test]
+    fn set_the_package_name() {
+        let cargo_toml = r#"[package]
+name = "héllo"
+"#;
+
+        let got = add_wapm_metadata(cargo_toml, "wasmer", Some("h-llo"), "wasi", false).unwrap();
+
+        assert_eq!(
+            got,
+            r#"[package]
+name = "héllo"
+
+[package.metadata.wapm]
+namespace = "wasmer"
+package = "h-llo"
+abi = "wasi"
+"#
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::{Context, Error};
+use clap::Parser;
+
+use crate::publish::BuildOptions;
+
+/// Print the `wapm.toml` that would be generated for a crate.
+#[derive(Debug, Parser)]
+#[clap(author)]
+pub struct Inspect {
+    #[clap(flatten)]
+    pub build: BuildOptions,
+}
+
+impl Inspect {
+    /// Run the [`Inspect`] command.
+    pub fn execute(self) -> Result<(), Error> {
+        let metadata = self.build.metadata()?;
+        let packages = self.build.packages(&metadata)?;
+
+        for (i, pkg) in packages.into_iter().enumerate() {
+            let (manifest, _) =
+                crate::publish::prepare(pkg, &metadata, &self.build).with_context(|| {
+                    format!("Unable to generate the wapm.toml for \"{}\"", pkg.name)
+                })?;
+            let toml = toml::to_string(&manifest).context("Unable to serialize the wapm.toml")?;
+
+            if i > 0 {
+                println!();
+            }
+            println!("# {}", pkg.manifest_path);
+            print!("{}", toml);
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,30 @@
+mod archive;
+mod build;
+mod check;
+mod container;
+#[cfg(test)]
+mod fixtures;
+mod init;
+mod inspect;
 mod metadata;
+mod optimize;
+mod package;
 mod publish;
+mod registry;
+mod strip;
+mod validate;
+mod workspace;
 
 pub use crate::{
-    metadata::{Features, MetadataTable, Wapm},
-    publish::Publish,
+    build::Build,
+    check::Check,
+    init::Init,
+    inspect::Inspect,
+    metadata::{
+        CommandConfig, Features, MetadataTable, OptimizeConfig, Strip, StripPreset, StripSections,
+        Wapm,
+    },
+    optimize::OptimizationLevel,
+    package::{Format, Package},
+    publish::{BuildOptions, Publish},
 };
@@ -1,28 +1,264 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
 };
 
 use anyhow::Error;
-use cargo_metadata::{CargoOpt, Metadata, MetadataCommand};
+use cargo_metadata::{CargoOpt, Metadata, MetadataCommand, Package};
+use serde::Deserialize;
 use wapm_toml::Bindings;
 
+use crate::OptimizationLevel;
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct MetadataTable {
     pub wapm: Wapm,
 }
 
+impl MetadataTable {
+    /// Load a package's `[package.metadata.wapm]` table, using the
+    /// workspace's `[workspace.metadata.wapm]` table for any fields the
+    /// package doesn't set.
+    pub(crate) fn for_package(
+        pkg: &Package,
+        metadata: &Metadata,
+    ) -> Result<Self, serde_json::Error> {
+        let merged = with_workspace_defaults(
+            &metadata.workspace_metadata,
+            &pkg.metadata,
+            metadata.workspace_root.as_std_path(),
+        )?;
+        MetadataTable::deserialize(merged)
+    }
+}
+
+/// The `[workspace.metadata.wapm]` fields packages can inherit. Everything
+/// else (e.g. `package` or `commands`) only makes sense for a single crate.
+const WORKSPACE_KEYS: &[&str] = &[
+    "namespace",
+    "abi",
+    "wasmer-extra-flags",
+    "features",
+    "optimize",
+    "strip",
+    "target",
+];
+
+/// Merge the `wapm` tables from the workspace and package metadata, letting
+/// the package override individual fields.
+///
+/// Paths in the workspace table are relative to the workspace root.
+fn with_workspace_defaults(
+    workspace_metadata: &serde_json::Value,
+    package_metadata: &serde_json::Value,
+    workspace_root: &Path,
+) -> Result<serde_json::Value, serde_json::Error> {
+    let mut wapm = serde_json::Map::new();
+
+    if let Some(table) = workspace_metadata.get("wapm").and_then(|w| w.as_object()) {
+        for (key, value) in table {
+            if !WORKSPACE_KEYS.contains(&key.as_str()) {
+                return Err(serde::de::Error::custom(format!(
+                    "\"{}\" can't be set in [workspace.metadata.wapm] because it only applies to individual crates",
+                    key
+                )));
+            }
+
+            let value = match value.as_str() {
+                Some(target) if key == "target" && crate::publish::is_custom_target(target) => {
+                    serde_json::Value::from(workspace_root.join(target).display().to_string())
+                }
+                _ => value.clone(),
+            };
+            wapm.insert(key.clone(), value);
+        }
+    }
+
+    if let Some(table) = package_metadata.get("wapm").and_then(|w| w.as_object()) {
+        wapm.extend(table.clone());
+    }
+
+    Ok(serde_json::json!({ "wapm": wapm }))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Wapm {
+    /// The namespace the package is published under.
     pub namespace: String,
+    /// The package's name, if it should be different from the crate's name.
+    #[serde(default)]
     pub package: Option<String>,
     pub wasmer_extra_flags: Option<String>,
     pub abi: wapm_toml::Abi,
+    /// The target triple (or path to a custom target JSON file) to compile
+    /// for, instead of the one implied by the `abi`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub fs: Option<HashMap<String, PathBuf>>,
     pub bindings: Option<Bindings>,
+    /// The binaries to publish. If neither this nor `lib` are set, every
+    /// binary and `cdylib` target will be published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bins: Option<Vec<String>>,
+    /// Should the `cdylib` target be published?
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lib: Option<bool>,
+    /// Cargo features which should always be enabled when compiling this
+    /// package.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+    /// Run `wasm-opt` over each module after it has been compiled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optimize: Option<OptimizeConfig>,
+    /// Custom sections to remove from each module before it is packaged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip: Option<Strip>,
+    /// Explicitly configured commands, keyed by the command's name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commands: Option<BTreeMap<String, CommandConfig>>,
+    /// Other WAPM packages this package depends on at runtime, mapping a
+    /// `namespace/name` to a version requirement.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dependencies: Option<HashMap<String, String>>,
+    /// Crates from the same workspace which this package uses at runtime
+    /// rather than linking into its own binary.
+    ///
+    /// Each of these must be a path dependency with its own
+    /// `[package.metadata.wapm]` table, and will be added to the generated
+    /// manifest's dependencies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime_dependencies: Option<Vec<String>>,
+}
+
+/// The `[package.metadata.wapm.commands.<name>]` table.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommandConfig {
+    /// The module this command will run. Defaults to the command's name.
+    pub module: Option<String>,
+    /// Arguments that will be passed to the module before any user-provided
+    /// arguments.
+    pub main_args: Option<String>,
+    /// The runner used to execute this command, either as a URI or one of
+    /// the well-known short names (`wasi`, `wcgi`, or `emscripten`).
+    ///
+    /// Setting a runner will emit the command using the newer V2 format.
+    pub runner: Option<String>,
+    /// Extra information that is passed to the runner executing this command.
+    ///
+    /// This is stored as JSON rather than TOML so the table can be compared
+    /// with `Eq`.
+    pub annotations: Option<serde_json::Value>,
+}
+
+/// Check a namespace or package name for characters the registry is likely
+/// to reject, returning a warning (with a suggested alternative, if possible)
+/// when there is a problem.
+///
+/// This is only a heuristic, so the registry gets the final say.
+pub(crate) fn name_warning(kind: &str, name: &str) -> Option<String> {
+    let problem = name_problem(name)?;
+
+    let warning = match suggest_name(name) {
+        Some(suggestion) => format!(
+            "\"{}\" may not be a valid {} because {}. Try \"{}\" instead",
+            name, kind, problem, suggestion
+        ),
+        None => format!(
+            "\"{}\" may not be a valid {} because {}",
+            name, kind, problem
+        ),
+    };
+
+    Some(warning)
+}
+
+fn name_problem(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("it is empty")
+    } else if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        Some("it doesn't start with a letter")
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Some("it may only contain letters, numbers, hyphens, and underscores")
+    } else {
+        None
+    }
+}
+
+/// Turn an invalid name into something the registry should accept by
+/// replacing unsupported characters with hyphens (e.g. `my tool` becomes
+/// `my-tool`).
+pub(crate) fn suggest_name(name: &str) -> Option<String> {
+    let mut suggestion = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            suggestion.push(c);
+        } else if !suggestion.is_empty() && !suggestion.ends_with('-') {
+            suggestion.push('-');
+        }
+    }
+
+    let suggestion = suggestion.trim_end_matches('-');
+
+    if suggestion != name && name_problem(suggestion).is_none() {
+        Some(suggestion.to_string())
+    } else {
+        None
+    }
+}
+
+/// The `[package.metadata.wapm.optimize]` table.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OptimizeConfig {
+    /// How aggressively the modules should be optimized.
+    #[serde(default)]
+    pub level: OptimizationLevel,
+    /// Extra arguments to pass to `wasm-opt` (e.g. `--enable-bulk-memory`).
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// The `strip` setting, either the name of a preset or a table listing which
+/// custom sections to remove.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Strip {
+    /// A well-known set of sections (e.g. `strip = "debuginfo"`).
+    Preset(StripPreset),
+    /// An explicit `[package.metadata.wapm.strip]` table.
+    Sections(StripSections),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StripPreset {
+    /// Remove DWARF debug info (the `.debug_*` sections).
+    Debuginfo,
+    /// Remove debug info and the `name` section.
+    Symbols,
+    /// Remove every custom section.
+    All,
+}
+
+/// Explicitly list the custom sections to remove. A trailing `*` matches any
+/// section starting with that prefix.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StripSections {
+    /// Sections which should be removed.
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Sections which should be kept, even if they match `remove`.
+    #[serde(default)]
+    pub keep: Vec<String>,
 }
 
 #[tracing::instrument(skip_all)]
@@ -31,8 +267,10 @@ pub(crate) fn parse_cargo_toml(
     no_default_features: bool,
     features: Option<&Features>,
     all_features: bool,
+    other_options: &[String],
 ) -> Result<Metadata, Error> {
     let mut cmd = MetadataCommand::new();
+    cmd.other_options(other_options.to_vec());
 
     if let Some(manifest_path) = manifest_path {
         cmd.manifest_path(manifest_path);
@@ -91,12 +329,21 @@ mod tests {
                 package: None,
                 wasmer_extra_flags: None,
                 abi: wapm_toml::Abi::None,
+                target: None,
                 fs: None,
                 bindings: Some(Bindings::Wai(WaiBindings {
                     exports: Some("hello-world.wai".into()),
                     imports: Vec::new(),
                     wai_version: "0.1.0".parse().unwrap(),
                 })),
+                bins: None,
+                lib: None,
+                features: None,
+                optimize: None,
+                strip: None,
+                commands: None,
+                dependencies: None,
+                runtime_dependencies: None,
             },
         };
 
@@ -119,11 +366,20 @@ mod tests {
                 package: None,
                 wasmer_extra_flags: None,
                 abi: wapm_toml::Abi::None,
+                target: None,
                 fs: None,
                 bindings: Some(Bindings::Wit(WitBindings {
                     wit_bindgen: "0.1.0".parse().unwrap(),
                     wit_exports: "hello-world.wit".into(),
                 })),
+                bins: None,
+                lib: None,
+                features: None,
+                optimize: None,
+                strip: None,
+                commands: None,
+                dependencies: None,
+                runtime_dependencies: None,
             },
         };
 
@@ -131,4 +387,219 @@ mod tests {
 
         assert_eq!(got, should_be);
     }
+
+    #[test]
+    fn parse_target_selection() {
+        let table = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            abi = "wasi"
+            bins = ["foo", "foo-server"]
+            lib = false
+        };
+
+        let got = MetadataTable::deserialize(table).unwrap();
+
+        assert_eq!(
+            got.wapm.bins,
+            Some(vec!["foo".to_string(), "foo-server".to_string()])
+        );
+        assert_eq!(got.wapm.lib, Some(false));
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        let table = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            abi = "wasi"
+
+            [wapm.dependencies]
+            "wasmer/python" = "^3.12"
+        };
+
+        let got = MetadataTable::deserialize(table).unwrap();
+
+        let mut should_be = HashMap::new();
+        should_be.insert("wasmer/python".to_string(), "^3.12".to_string());
+        assert_eq!(got.wapm.dependencies, Some(should_be));
+    }
+
+    #[test]
+    fn parse_commands() {
+        let table = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            abi = "wasi"
+
+            [wapm.commands.mytool]
+            module = "mytool-cli"
+            main-args = "--verbose"
+
+            [wapm.commands.serve]
+            runner = "wcgi"
+
+            [wapm.commands.serve.annotations.wasi]
+            env = ["PORT=8080"]
+        };
+
+        let got = MetadataTable::deserialize(table).unwrap();
+
+        let commands = got.wapm.commands.unwrap();
+        assert_eq!(
+            commands["mytool"],
+            CommandConfig {
+                module: Some("mytool-cli".to_string()),
+                main_args: Some("--verbose".to_string()),
+                runner: None,
+                annotations: None,
+            }
+        );
+        assert_eq!(commands["serve"].runner.as_deref(), Some("wcgi"));
+        assert_eq!(
+            commands["serve"].annotations,
+            Some(serde_json::json!({ "wasi": { "env": ["PORT=8080"] } }))
+        );
+    }
+
+    #[test]
+    fn name_warnings() {
+        let valid = ["wasmer", "Michael-F-Bryan", "hello_world", "python3", "a"];
+        for name in valid {
+            assert_eq!(name_warning("namespace", name), None, "{}", name);
+        }
+
+        let invalid = [
+            ("", None),
+            ("my tool", Some("my-tool")),
+            ("wasmer.io", Some("wasmer-io")),
+            ("3d-printer", None),
+            ("-leading", Some("leading")),
+        ];
+        for (name, suggestion) in invalid {
+            assert_eq!(suggest_name(name).as_deref(), suggestion, "{}", name);
+            assert!(name_warning("namespace", name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn unusual_names_can_still_be_deserialized() {
+        let table = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            package = "my.tool"
+            abi = "none"
+        };
+
+        let got = MetadataTable::deserialize(table).unwrap();
+
+        assert_eq!(got.wapm.package.as_deref(), Some("my.tool"));
+        assert_eq!(
+            name_warning("package name", "my.tool").unwrap(),
+            "\"my.tool\" may not be a valid package name because it may only contain letters, numbers, hyphens, and underscores. Try \"my-tool\" instead"
+        );
+    }
+
+    #[test]
+    fn inherit_defaults_from_the_workspace() {
+        let workspace = serde_json::json!({
+            "wapm": { "namespace": "our-org", "abi": "wasi", "features": ["wasm"] },
+        });
+        let package = serde_json::json!({
+            "wapm": { "abi": "none", "package": "my-tool" },
+            "docs": { "rs": { "all-features": true } },
+        });
+
+        let merged = with_workspace_defaults(&workspace, &package, Path::new("/ws")).unwrap();
+        let got = MetadataTable::deserialize(merged).unwrap();
+
+        assert_eq!(got.wapm.namespace, "our-org");
+        assert_eq!(got.wapm.abi, wapm_toml::Abi::None);
+        assert_eq!(got.wapm.package.as_deref(), Some("my-tool"));
+        assert_eq!(got.wapm.features, Some(vec!["wasm".to_string()]));
+    }
+
+    #[test]
+    fn workspace_custom_targets_are_relative_to_the_workspace_root() {
+        let workspace = serde_json::json!({
+            "wapm": { "namespace": "our-org", "abi": "none", "target": "targets/wasm.json" },
+        });
+        let package = serde_json::json!({ "wapm": {} });
+
+        let merged = with_workspace_defaults(&workspace, &package, Path::new("/ws")).unwrap();
+        let got = MetadataTable::deserialize(merged).unwrap();
+
+        let expected = Path::new("/ws").join("targets/wasm.json");
+        assert_eq!(got.wapm.target, Some(expected.display().to_string()));
+    }
+
+    #[test]
+    fn per_crate_keys_are_rejected_at_the_workspace_level() {
+        let workspace = serde_json::json!({
+            "wapm": { "namespace": "our-org", "abi": "wasi", "package": "shared" },
+        });
+        let package = serde_json::json!({ "wapm": {} });
+
+        let err = with_workspace_defaults(&workspace, &package, Path::new("/ws")).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "\"package\" can't be set in [workspace.metadata.wapm] because it only applies to individual crates"
+        );
+    }
+
+    #[test]
+    fn parse_optimization_settings() {
+        let table = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            abi = "none"
+
+            [wapm.optimize]
+            level = "-Oz"
+        };
+
+        let got = MetadataTable::deserialize(table).unwrap();
+
+        assert_eq!(
+            got.wapm.optimize,
+            Some(OptimizeConfig {
+                level: OptimizationLevel::Oz,
+                args: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_strip_settings() {
+        let preset = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            abi = "none"
+            strip = "debuginfo"
+        };
+        let sections = toml::toml! {
+            [wapm]
+            namespace = "wasmer"
+            abi = "none"
+
+            [wapm.strip]
+            remove = ["producers"]
+        };
+
+        let preset = MetadataTable::deserialize(preset).unwrap();
+        let sections = MetadataTable::deserialize(sections).unwrap();
+
+        assert_eq!(
+            preset.wapm.strip,
+            Some(Strip::Preset(StripPreset::Debuginfo))
+        );
+        assert_eq!(
+            sections.wapm.strip,
+            Some(Strip::Sections(StripSections {
+                remove: vec!["producers".to_string()],
+                keep: Vec::new(),
+            }))
+        );
+    }
 }
@@ -0,0 +1,176 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Error};
+use clap::ValueEnum;
+
+use crate::OptimizeConfig;
+
+/// The optimization levels understood by `wasm-opt`.
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, ValueEnum,
+)]
+pub enum OptimizationLevel {
+    /// Run the default optimization passes.
+    #[default]
+    #[serde(rename = "O", alias = "-O")]
+    #[value(name = "O")]
+    O,
+    /// Quick optimizations.
+    #[serde(rename = "O1", alias = "-O1")]
+    #[value(name = "O1")]
+    O1,
+    /// Most optimizations.
+    #[serde(rename = "O2", alias = "-O2")]
+    #[value(name = "O2")]
+    O2,
+    /// Spend time optimizing, favouring speed.
+    #[serde(rename = "O3", alias = "-O3")]
+    #[value(name = "O3")]
+    O3,
+    /// The same as `O3`, with even more aggressive inlining.
+    #[serde(rename = "O4", alias = "-O4")]
+    #[value(name = "O4")]
+    O4,
+    /// Optimize for size.
+    #[serde(rename = "Os", alias = "-Os")]
+    #[value(name = "Os")]
+    Os,
+    /// Optimize aggressively for size.
+    #[serde(rename = "Oz", alias = "-Oz")]
+    #[value(name = "Oz")]
+    Oz,
+}
+
+impl OptimizationLevel {
+    fn flag(self) -> &'static str {
+        match self {
+            OptimizationLevel::O => "-O",
+            OptimizationLevel::O1 => "-O1",
+            OptimizationLevel::O2 => "-O2",
+            OptimizationLevel::O3 => "-O3",
+            OptimizationLevel::O4 => "-O4",
+            OptimizationLevel::Os => "-Os",
+            OptimizationLevel::Oz => "-Oz",
+        }
+    }
+}
+
+/// Run `wasm-opt` over each of the compiled modules, returning the paths to
+/// the optimized copies.
+#[tracing::instrument(skip_all)]
+pub(crate) fn optimize(
+    wasm_paths: &[PathBuf],
+    config: &OptimizeConfig,
+) -> Result<Vec<PathBuf>, Error> {
+    let wasm_opt = wasm_opt_bin();
+
+    wasm_paths
+        .iter()
+        .map(|path| optimize_module(&wasm_opt, path, config))
+        .collect()
+}
+
+fn optimize_module(wasm_opt: &str, path: &Path, config: &OptimizeConfig) -> Result<PathBuf, Error> {
+    // Note: we don't want to overwrite cargo's own output
+    let output = path.with_extension("opt.wasm");
+
+    let mut cmd = Command::new(wasm_opt);
+    cmd.arg(config.level.flag())
+        .args(&config.args)
+        .arg(path)
+        .arg("-o")
+        .arg(&output);
+
+    tracing::debug!(?cmd, "Optimizing");
+
+    let status = cmd.status().with_context(|| {
+        format!(
+            "Unable to start \"{}\". Is it installed?",
+            cmd.get_program().to_string_lossy()
+        )
+    })?;
+
+    if !status.success() {
+        match status.code() {
+            Some(code) => anyhow::bail!("wasm-opt exited unsuccessfully with exit code {}", code),
+            None => anyhow::bail!("wasm-opt exited unsuccessfully"),
+        }
+    }
+
+    let before = file_size(path)?;
+    let after = file_size(&output)?;
+    tracing::info!(
+        module = %path.display(),
+        before,
+        after,
+        "Optimized",
+    );
+
+    Ok(output)
+}
+
+fn file_size(path: &Path) -> Result<u64, Error> {
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Unable to read the metadata for \"{}\"", path.display()))?;
+    Ok(metadata.len())
+}
+
+fn wasm_opt_bin() -> String {
+    std::env::var("WASM_OPT").unwrap_or_else(|_| String::from("wasm-opt"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn optimize_with_wasm_opt() {
+        let dir = tempfile::tempdir().unwrap();
+        // A fake wasm-opt which records its arguments and truncates the input
+        let wasm_opt = dir.path().join("wasm-opt");
+        let args_file = dir.path().join("args.txt");
+        std::fs::write(
+            &wasm_opt,
+            format!(
+                r#"#!/bin/sh
+echo "$@" > "{}"
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -o) output="$2"; shift ;;
+        *.wasm) input="$1" ;;
+    esac
+    shift
+done
+head -c 8 "$input" > "$output"
+"#,
+                args_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&wasm_opt, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let module = dir.path().join("my-tool.wasm");
+        std::fs::write(&module, b"\0asm\x01\0\0\0 plus some padding").unwrap();
+        let config = OptimizeConfig {
+            level: OptimizationLevel::Oz,
+            args: vec!["--strip-debug".to_string()],
+        };
+
+        let got = optimize_module(wasm_opt.to_str().unwrap(), &module, &config).unwrap();
+
+        assert_eq!(got, dir.path().join("my-tool.opt.wasm"));
+        assert_eq!(std::fs::read(&got).unwrap(), b"\0asm\x01\0\0\0");
+        assert_eq!(
+            std::fs::read_to_string(&args_file).unwrap().trim(),
+            format!(
+                "-Oz --strip-debug {} -o {}",
+                module.display(),
+                got.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{Context, Error};
+use clap::{Parser, ValueEnum};
+
+use crate::publish::BuildOptions;
+
+/// Compile a crate and bundle it into a single package file.
+#[derive(Debug, Parser)]
+#[clap(author)]
+pub struct Package {
+    #[clap(flatten)]
+    pub build: BuildOptions,
+    /// The kind of file to create.
+    #[clap(long, value_enum, default_value_t = Format::TarGz)]
+    pub format: Format,
+}
+
+/// The file formats a package can be bundled into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A `*.tar.gz` archive, as uploaded to the registry.
+    TarGz,
+    /// A WebC container, which can be run directly by the Wasmer runtime.
+    Webc,
+}
+
+impl Package {
+    /// Run the [`Package`] command.
+    pub fn execute(self) -> Result<(), Error> {
+        let metadata = self.build.metadata()?;
+        let packages = self.build.packages(&metadata)?;
+
+        let dir = self.build.target_dir(&metadata).join("wapm");
+
+        for pkg in packages {
+            let _span = tracing::info_span!("package", pkg = pkg.name.as_str()).entered();
+
+            let dest = dir.join(&pkg.name);
+            let (manifest, targets) = crate::publish::prepare(pkg, &metadata, &self.build)?;
+            crate::publish::build(pkg, &metadata, &dest, &manifest, &targets, &self.build)
+                .with_context(|| format!("Unable to package \"{}\"", pkg.name))?;
+
+            let (bytes, extension) = match self.format {
+                Format::TarGz => (crate::archive::create_archive(&dest)?, "tar.gz"),
+                Format::Webc => (crate::container::create_webc(&dest)?, "webc"),
+            };
+            let path = dir.join(format!(
+                "{}-{}.{}",
+                pkg.name, manifest.package.version, extension
+            ));
+            std::fs::write(&path, &bytes)
+                .with_context(|| format!("Unable to write to \"{}\"", path.display()))?;
+
+            tracing::info!(path = %path.display(), bytes = bytes.len(), "Created the package");
+        }
+
+        Ok(())
+    }
+}
@@ -1,27 +1,60 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
     process::Command,
+    sync::OnceLock,
 };
 
 use anyhow::{Context, Error};
-use cargo_metadata::{Metadata, Package, Target};
-use clap::Parser;
-use serde::Deserialize;
-use wapm_toml::{Manifest, Module};
+use cargo_metadata::{
+    semver::{Prerelease, Version, VersionReq},
+    Metadata, Package, Target,
+};
+use clap::{Args, Parser};
+use wapm_toml::{CommandAnnotations, Manifest, Module};
 
-use crate::{metadata::Features, MetadataTable, Wapm};
+use crate::{
+    metadata::Features,
+    registry::{GraphQLRegistry, Registry, Upload, DEFAULT_REGISTRY},
+    CommandConfig, MetadataTable, OptimizationLevel, OptimizeConfig, Wapm,
+};
 
 /// Publish a crate to the WebAssembly Package Manager.
 #[derive(Debug, Parser)]
-#[clap(author, version, about)]
+#[clap(author)]
 pub struct Publish {
     /// Build the package, but don't publish it.
     #[clap(short, long, env)]
     pub dry_run: bool,
+    #[clap(flatten)]
+    pub build: BuildOptions,
+    /// Keep publishing the remaining packages when one fails, printing a
+    /// summary at the end.
+    #[clap(long)]
+    pub keep_going: bool,
+    /// Skip any packages which were published by a previous run that didn't
+    /// finish.
+    #[clap(long)]
+    pub resume: bool,
+    /// Don't fail when a package's version has already been published.
+    #[clap(long)]
+    pub skip_existing: bool,
+    /// The GraphQL endpoint for the registry being published to.
+    #[clap(long, env = "WAPM_REGISTRY", default_value = DEFAULT_REGISTRY)]
+    pub registry: String,
+    /// The API token used when publishing. Defaults to the token saved by
+    /// "wapm login".
+    #[clap(long, env = "WAPM_REGISTRY_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+/// Options shared by every command that compiles a crate.
+#[derive(Debug, Default, Args)]
+pub struct BuildOptions {
     /// Path to Cargo.toml
     #[clap(long, env)]
     pub manifest_path: Option<PathBuf>,
-    /// Publish every crate in this workspace
+    /// Use every crate in this workspace
     #[clap(short, long, env)]
     pub workspace: bool,
     /// A comma-delimited list of features to enable.
@@ -37,122 +70,756 @@ pub struct Publish {
     #[clap(long)]
     pub exclude: Vec<String>,
     /// Compile in debug mode.
-    #[clap(long)]
+    #[clap(long, conflicts_with = "profile")]
     pub debug: bool,
+    /// Compile with the specified profile (e.g. a size-optimized
+    /// "wasm-release" profile).
+    #[clap(long, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Directory for all generated artifacts.
+    #[clap(long, value_name = "DIR")]
+    pub target_dir: Option<PathBuf>,
+    /// Compile for this target triple (or custom target JSON file) instead
+    /// of the one implied by the ABI.
+    #[clap(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+    /// Require Cargo.lock to be up to date.
+    #[clap(long)]
+    pub locked: bool,
+    /// Require Cargo.lock and the cache to be up to date.
+    #[clap(long)]
+    pub frozen: bool,
+    /// Run without accessing the network.
+    #[clap(long)]
+    pub offline: bool,
+    /// Optimize the compiled modules with wasm-opt, overriding the level set
+    /// in Cargo.toml.
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "O"
+    )]
+    pub optimize: Option<OptimizationLevel>,
+    /// Unstable (nightly-only) flags to pass to cargo.
+    #[clap(short = 'Z', value_name = "FLAG")]
+    pub unstable_flags: Vec<String>,
+    /// Only include the specified binary (may be repeated).
+    #[clap(long = "bin", value_name = "NAME")]
+    pub bins: Vec<String>,
+    /// Only include this package's "cdylib" library.
+    #[clap(long)]
+    pub lib: bool,
+    /// Use this namespace instead of the one in Cargo.toml.
+    #[clap(long, env = "WAPM_NAMESPACE")]
+    pub namespace: Option<String>,
+    /// Use this package name instead of the one in Cargo.toml (only when
+    /// publishing a single crate).
+    #[clap(long)]
+    pub package_name: Option<String>,
+    /// Publish with this version instead of the one in Cargo.toml (only
+    /// when publishing a single crate).
+    #[clap(long, value_name = "VERSION")]
+    pub package_version: Option<Version>,
+    /// A pre-release suffix to add to the version (e.g. "nightly.20230101").
+    #[clap(long, value_name = "SUFFIX", value_parser = parse_version_suffix)]
+    pub version_suffix: Option<Prerelease>,
+    /// Extra arguments to pass to "cargo build".
+    #[clap(last = true, value_name = "CARGO_ARGS")]
+    pub cargo_args: Vec<String>,
 }
 
-impl Publish {
-    /// Run the [`Publish`] command.
-    pub fn execute(self) -> Result<(), Error> {
-        let metadata = crate::metadata::parse_cargo_toml(
+impl BuildOptions {
+    pub(crate) fn metadata(&self) -> Result<Metadata, Error> {
+        crate::metadata::parse_cargo_toml(
             self.manifest_path.as_deref(),
             self.no_default_features,
             self.features.as_ref(),
             self.all_features,
+            &self.lockfile_flags(),
         )
-        .context("Unable to parse the workspace's metadata")?;
+        .context("Unable to parse the workspace's metadata")
+    }
+
+    /// The `--locked`, `--frozen`, and `--offline` flags to pass to cargo.
+    fn lockfile_flags(&self) -> Vec<String> {
+        [
+            ("--locked", self.locked),
+            ("--frozen", self.frozen),
+            ("--offline", self.offline),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(flag, _)| flag.to_string())
+        .collect()
+    }
+
+    /// The directory compiled artifacts are written to.
+    pub(crate) fn target_dir(&self, metadata: &Metadata) -> PathBuf {
+        self.target_dir
+            .clone()
+            .unwrap_or_else(|| metadata.target_directory.clone().into())
+    }
+
+    /// The features to enable when compiling a package, combining those
+    /// from the command-line with the package's own `features`.
+    fn features_for(&self, wapm: &Wapm) -> Vec<String> {
+        let mut features: Vec<String> = wapm.features.clone().unwrap_or_default();
+
+        for feature in self.features.iter().flat_map(|f| &f.0) {
+            if !features.contains(feature) {
+                features.push(feature.clone());
+            }
+        }
+
+        features
+    }
+
+    /// How the compiled modules should be optimized, if at all.
+    fn optimization(&self, wapm: &Wapm) -> Option<OptimizeConfig> {
+        match (self.optimize, &wapm.optimize) {
+            (Some(level), config) => Some(OptimizeConfig {
+                level,
+                ..config.clone().unwrap_or_default()
+            }),
+            (None, config) => config.clone(),
+        }
+    }
+
+    /// Apply the `--namespace` and `--package-name` overrides to a package's
+    /// metadata.
+    pub(crate) fn apply_overrides(&self, wapm: &mut Wapm) {
+        if let Some(namespace) = &self.namespace {
+            wapm.namespace = namespace.clone();
+        }
+        if let Some(package_name) = &self.package_name {
+            wapm.package = Some(package_name.clone());
+        }
+    }
+
+    /// The target triple to compile a package for.
+    ///
+    /// Custom target JSON files from `Cargo.toml` are relative to the
+    /// crate's directory.
+    fn target_triple(&self, wapm: &Wapm, base_dir: &Path) -> String {
+        if let Some(target) = &self.target {
+            return target.clone();
+        }
+
+        match &wapm.target {
+            Some(target) if is_custom_target(target) => base_dir.join(target).display().to_string(),
+            Some(target) => target.clone(),
+            None => default_target(wapm.abi, base_dir).to_string(),
+        }
+    }
+
+    /// The name of the cargo profile to compile with.
+    fn profile(&self) -> &str {
+        match &self.profile {
+            Some(profile) => profile,
+            None if self.debug => "dev",
+            None => "release",
+        }
+    }
 
+    /// Find the packages these options refer to, sorted so dependencies come
+    /// before the packages that depend on them.
+    pub(crate) fn packages<'meta>(
+        &self,
+        metadata: &'meta Metadata,
+    ) -> Result<Vec<&'meta Package>, Error> {
         let current_dir =
             std::env::current_dir().context("Unable to determine the current directory")?;
 
-        let packages_to_publish =
-            determine_crates_to_publish(&metadata, self.workspace, &current_dir, &self.exclude)
+        let packages =
+            determine_crates_to_publish(metadata, self.workspace, &current_dir, &self.exclude)
                 .context("Unable to determine which crates to publish")?;
 
-        let dir = metadata.target_directory.join("wapm");
+        anyhow::ensure!(
+            self.package_name.is_none() || packages.len() == 1,
+            "The --package-name flag can only be used with a single crate, but {} were selected",
+            packages.len()
+        );
+        anyhow::ensure!(
+            self.package_version.is_none() || packages.len() == 1,
+            "The --package-version flag can only be used with a single crate, but {} were selected",
+            packages.len()
+        );
+
+        crate::workspace::publish_order(&packages)
+    }
+
+    /// The version a package will be published with, after applying any
+    /// overrides.
+    pub(crate) fn version_for(&self, pkg: &Package) -> Version {
+        let version = self
+            .package_version
+            .clone()
+            .unwrap_or_else(|| pkg.version.clone());
+        self.with_version_suffix(version)
+    }
+
+    /// The version another crate from the workspace is published with.
+    ///
+    /// Unlike `--version-suffix`, the `--package-version` flag only applies to the
+    /// crate being published.
+    fn sibling_version(&self, pkg: &Package) -> Version {
+        self.with_version_suffix(pkg.version.clone())
+    }
+
+    fn with_version_suffix(&self, mut version: Version) -> Version {
+        if let Some(suffix) = &self.version_suffix {
+            version.pre = if version.pre.is_empty() {
+                suffix.clone()
+            } else {
+                let pre = format!("{}.{}", version.pre, suffix);
+                Prerelease::new(&pre).expect("Joining valid pre-release identifiers is valid")
+            };
+        }
+
+        version
+    }
+}
+
+fn parse_version_suffix(suffix: &str) -> Result<Prerelease, Error> {
+    anyhow::ensure!(!suffix.is_empty(), "The pre-release suffix can't be empty");
+    Prerelease::new(suffix)
+        .with_context(|| format!("\"{}\" isn't a valid pre-release suffix", suffix))
+}
+
+impl Publish {
+    /// Run the [`Publish`] command.
+    pub fn execute(self) -> Result<(), Error> {
+        let metadata = self.build.metadata()?;
+        let packages_to_publish = self.build.packages(&metadata)?;
+
+        let dir = self.build.target_dir(&metadata).join("wapm");
 
-        tracing::debug!(%dir, "Clearing the output directory");
+        tracing::debug!(dir = %dir.display(), "Clearing the output directory");
 
-        for pkg in packages_to_publish {
-            let dest: PathBuf = dir.join(&pkg.name).into();
-            publish(pkg, metadata.target_directory.as_ref(), &dest, &self)
-                .with_context(|| format!("Unable to publish \"{}\"", pkg.name))?;
+        let progress_file = dir.join(PROGRESS_FILE);
+        let mut progress = if self.resume {
+            Progress::load(&progress_file)?
+        } else {
+            Progress::default()
+        };
+
+        let token = self
+            .token
+            .clone()
+            .or_else(|| crate::registry::token_from_wapm_config(&self.registry));
+        let registry = GraphQLRegistry::new(&self.registry).with_token(token);
+        let outcomes = publish_packages(
+            &packages_to_publish,
+            &self.build,
+            self.keep_going,
+            &mut progress,
+            // Dry runs don't publish anything, so there's nothing to resume
+            (!self.dry_run).then_some(progress_file.as_path()),
+            |pkg| publish(pkg, &metadata, &dir.join(&pkg.name), &registry, &self),
+        )?;
+
+        if self.keep_going {
+            print_summary(&outcomes, &self.build);
         }
 
+        let failures = outcomes.iter().filter(|(_, o)| o.is_failure()).count();
+        anyhow::ensure!(
+            failures == 0,
+            "{} of {} packages weren't published",
+            failures,
+            outcomes.len()
+        );
+
         Ok(())
     }
 }
 
+/// Publish each package in order, skipping any which were published by a
+/// previous run or which depend on a package that couldn't be published.
+///
+/// Successfully published packages are recorded in `progress`, and saved to
+/// `progress_file` if one is provided.
+fn publish_packages<'pkg>(
+    packages: &[&'pkg Package],
+    options: &BuildOptions,
+    keep_going: bool,
+    progress: &mut Progress,
+    progress_file: Option<&Path>,
+    mut publish_one: impl FnMut(&Package) -> Result<Outcome, Error>,
+) -> Result<Vec<(&'pkg Package, Outcome)>, Error> {
+    let mut outcomes: Vec<(&Package, Outcome)> = Vec::new();
+
+    for &pkg in packages {
+        let version = options.version_for(pkg);
+
+        if progress.contains(pkg, &version) {
+            tracing::info!(
+                pkg = pkg.name.as_str(),
+                "Skipping because it was published in a previous run"
+            );
+            outcomes.push((pkg, Outcome::AlreadyPublished));
+            continue;
+        }
+
+        let failed_dependency = crate::workspace::local_dependencies(pkg, packages)
+            .into_iter()
+            .find(|dep| {
+                outcomes
+                    .iter()
+                    .any(|(p, outcome)| p.id == dep.id && outcome.is_failure())
+            });
+        if let Some(dep) = failed_dependency {
+            tracing::warn!(
+                pkg = pkg.name.as_str(),
+                dependency = dep.name.as_str(),
+                "Skipping because a dependency wasn't published"
+            );
+            outcomes.push((pkg, Outcome::Skipped(dep.name.clone())));
+            continue;
+        }
+
+        match publish_one(pkg) {
+            Ok(outcome) => {
+                if let Some(progress_file) = progress_file {
+                    progress.record(pkg, &version);
+                    progress.save(progress_file)?;
+                }
+                outcomes.push((pkg, outcome));
+            }
+            Err(e) if keep_going => {
+                tracing::error!(pkg = pkg.name.as_str(), error = &*e, "Unable to publish");
+                outcomes.push((pkg, Outcome::Failed(e)));
+            }
+            Err(e) => {
+                return Err(e.context(format!("Unable to publish \"{}\"", pkg.name)));
+            }
+        }
+    }
+
+    Ok(outcomes)
+}
+
+/// The file used to keep track of which packages have already been published,
+/// so an interrupted run can be resumed.
+const PROGRESS_FILE: &str = "publish-progress.txt";
+
+/// The `name@version` of every package that was published during the current
+/// run (and any runs being resumed).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Progress {
+    published: Vec<String>,
+}
+
+impl Progress {
+    fn load(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(Progress {
+                published: contents.lines().map(String::from).collect(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!(
+                    path = %path.display(),
+                    "Nothing to resume because the progress file doesn't exist"
+                );
+                Ok(Progress::default())
+            }
+            Err(e) => Err(Error::from(e).context(format!("Unable to read \"{}\"", path.display()))),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Unable to create the \"{}\" directory", parent.display())
+            })?;
+        }
+
+        let mut contents = self.published.join("\n");
+        contents.push('\n');
+        std::fs::write(path, contents)
+            .with_context(|| format!("Unable to write to \"{}\"", path.display()))
+    }
+
+    fn key(pkg: &Package, version: &Version) -> String {
+        format!("{}@{}", pkg.name, version)
+    }
+
+    fn contains(&self, pkg: &Package, version: &Version) -> bool {
+        self.published.contains(&Progress::key(pkg, version))
+    }
+
+    fn record(&mut self, pkg: &Package, version: &Version) {
+        self.published.push(Progress::key(pkg, version));
+    }
+}
+
+/// What happened when we tried to publish a package.
+#[derive(Debug)]
+enum Outcome {
+    Published,
+    AlreadyPublished,
+    /// The package wasn't published because one of its dependencies failed.
+    Skipped(String),
+    Failed(Error),
+}
+
+impl Outcome {
+    fn is_failure(&self) -> bool {
+        matches!(self, Outcome::Failed(_) | Outcome::Skipped(_))
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Published => write!(f, "published"),
+            Outcome::AlreadyPublished => write!(f, "already published"),
+            Outcome::Skipped(dep) => write!(f, "skipped ({} wasn't published)", dep),
+            Outcome::Failed(e) => write!(f, "failed ({})", e),
+        }
+    }
+}
+
+fn print_summary(outcomes: &[(&Package, Outcome)], options: &BuildOptions) {
+    let name_width = outcomes
+        .iter()
+        .map(|(pkg, _)| pkg.name.len())
+        .chain(std::iter::once("Package".len()))
+        .max()
+        .unwrap_or_default();
+    let version_width = outcomes
+        .iter()
+        .map(|(pkg, _)| options.version_for(pkg).to_string().len())
+        .chain(std::iter::once("Version".len()))
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:<name_width$}  {:<version_width$}  Status",
+        "Package",
+        "Version",
+        name_width = name_width,
+        version_width = version_width
+    );
+
+    for (pkg, outcome) in outcomes {
+        println!(
+            "{:<name_width$}  {:<version_width$}  {}",
+            pkg.name,
+            options.version_for(pkg).to_string(),
+            outcome,
+            name_width = name_width,
+            version_width = version_width
+        );
+    }
+}
+
 #[tracing::instrument(fields(pkg = pkg.name.as_str()), skip_all)]
-fn publish(pkg: &Package, target_dir: &Path, dir: &Path, args: &Publish) -> Result<(), Error> {
+fn publish(
+    pkg: &Package,
+    metadata: &Metadata,
+    dir: &Path,
+    registry: &dyn Registry,
+    args: &Publish,
+) -> Result<Outcome, Error> {
     tracing::info!(dry_run = args.dry_run, "Publishing");
 
-    let target = determine_target(pkg)?;
-    let manifest: Manifest = generate_manifest(pkg, target)?;
-    let modules = manifest
-        .module
-        .as_deref()
-        .expect("We will always compile one module");
-    let wasm_path = compile_to_wasm(pkg, target_dir, args.debug, &modules[0], target)?;
-    pack(dir, &manifest, &wasm_path, pkg)?;
-    upload_to_wapm(dir, args.dry_run)?;
+    let (manifest, targets) = prepare(pkg, metadata, &args.build)?;
+
+    if args.skip_existing && is_already_published(registry, &manifest)? {
+        tracing::info!(
+            name = manifest.package.name.as_str(),
+            version = %manifest.package.version,
+            "Skipping because this version has already been published",
+        );
+        return Ok(Outcome::AlreadyPublished);
+    }
+
+    build(pkg, metadata, dir, &manifest, &targets, &args.build)?;
+    upload_to_wapm(dir, &manifest, registry, args.dry_run)?;
 
     tracing::info!("Published!");
 
-    Ok(())
+    Ok(Outcome::Published)
+}
+
+/// Generate the `wapm.toml` for a package and figure out which of its targets
+/// need to be compiled.
+pub(crate) fn prepare<'pkg>(
+    pkg: &'pkg Package,
+    metadata: &Metadata,
+    options: &BuildOptions,
+) -> Result<(Manifest, Vec<&'pkg Target>), Error> {
+    let mut wapm = load_wapm(pkg, metadata)?;
+    options.apply_overrides(&mut wapm);
+
+    for (_, warning) in name_warnings(pkg, &wapm) {
+        tracing::warn!("{}", warning);
+    }
+
+    if let Some(runtime_dependencies) = &wapm.runtime_dependencies {
+        let siblings = workspace_dependencies(metadata, pkg, runtime_dependencies, options)?;
+        let dependencies = wapm.dependencies.get_or_insert_with(HashMap::new);
+        for (name, version) in siblings {
+            // Explicitly declared dependencies take precedence
+            dependencies.entry(name).or_insert(version);
+        }
+    }
+
+    validate_description(pkg)?;
+    if let Some(dependencies) = &wapm.dependencies {
+        validate_dependencies(dependencies)?;
+    }
+
+    let targets = select_targets(pkg, &wapm, options)?;
+    let mut manifest = generate_manifest(pkg, wapm, &targets)?;
+    manifest.package.version = options.version_for(pkg);
+
+    Ok((manifest, targets))
+}
+
+/// Compile a package's `targets` to WebAssembly and lay out everything that
+/// goes into the package inside `dir`.
+pub(crate) fn build(
+    pkg: &Package,
+    metadata: &Metadata,
+    dir: &Path,
+    manifest: &Manifest,
+    targets: &[&Target],
+    options: &BuildOptions,
+) -> Result<(), Error> {
+    let wasm_paths = build_modules(pkg, metadata, manifest, targets, options)?;
+    pack(dir, manifest, &wasm_paths, pkg)
+}
+
+/// Compile, optimize, and strip a package's `targets`, returning the path to
+/// each finished `*.wasm` file.
+pub(crate) fn build_modules(
+    pkg: &Package,
+    metadata: &Metadata,
+    manifest: &Manifest,
+    targets: &[&Target],
+    options: &BuildOptions,
+) -> Result<Vec<PathBuf>, Error> {
+    let wapm = load_wapm(pkg, metadata)?;
+    let mut wasm_paths = compile(pkg, metadata, manifest, targets, options)?;
+
+    if let Some(config) = options.optimization(&wapm) {
+        wasm_paths = crate::optimize::optimize(&wasm_paths, &config)?;
+    }
+
+    if let Some(strip) = &wapm.strip {
+        wasm_paths = wasm_paths
+            .iter()
+            .map(|path| {
+                // Note: we don't want to overwrite cargo's own output
+                let dest = path.with_extension("stripped.wasm");
+                crate::strip::strip_custom_sections(path, &dest, strip)?;
+                Ok(dest)
+            })
+            .collect::<Result<_, Error>>()?;
+    }
+
+    Ok(wasm_paths)
+}
+
+fn load_wapm(pkg: &Package, metadata: &Metadata) -> Result<Wapm, Error> {
+    let MetadataTable { wapm } = MetadataTable::for_package(pkg, metadata)
+        .context("Unable to deserialize the [metadata] table")?;
+    Ok(wapm)
+}
+
+/// Compile a package's `targets` to WebAssembly, returning the path to each
+/// `*.wasm` file.
+pub(crate) fn compile(
+    pkg: &Package,
+    metadata: &Metadata,
+    manifest: &Manifest,
+    targets: &[&Target],
+    options: &BuildOptions,
+) -> Result<Vec<PathBuf>, Error> {
+    let wapm = load_wapm(pkg, metadata)?;
+    let base_dir = pkg.manifest_path.parent().unwrap().as_std_path();
+    let features = options.features_for(&wapm);
+    let target_triple = options.target_triple(&wapm, base_dir);
+
+    let wasm_paths = compile_to_wasm(
+        pkg,
+        &options.target_dir(metadata),
+        &target_triple,
+        targets,
+        &features,
+        options,
+    )?;
+
+    crate::validate::validate_modules(manifest, targets, &wasm_paths, base_dir)?;
+
+    Ok(wasm_paths)
+}
+
+fn is_already_published(registry: &dyn Registry, manifest: &Manifest) -> Result<bool, Error> {
+    let wapm_toml::Package { name, version, .. } = &manifest.package;
+
+    registry.version_exists(name, version).with_context(|| {
+        format!(
+            "Unable to check whether version {} of \"{}\" has already been published",
+            version, name
+        )
+    })
+}
+
+/// Which of a package's targets should be published.
+///
+/// An empty selection means every binary and `cdylib` target.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct TargetSelection {
+    bins: Vec<String>,
+    lib: bool,
+}
+
+impl TargetSelection {
+    fn from_metadata(wapm: &Wapm) -> Self {
+        TargetSelection {
+            bins: wapm.bins.clone().unwrap_or_default(),
+            lib: wapm.lib.unwrap_or(false),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bins.is_empty() && !self.lib
+    }
+}
+
+/// Find the targets to publish, preferring any targets selected on the
+/// command-line over those in the `[package.metadata.wapm]` table.
+pub(crate) fn select_targets<'pkg>(
+    pkg: &'pkg Package,
+    wapm: &Wapm,
+    options: &BuildOptions,
+) -> Result<Vec<&'pkg Target>, Error> {
+    let selection = if options.bins.is_empty() && !options.lib {
+        TargetSelection::from_metadata(wapm)
+    } else {
+        TargetSelection {
+            bins: options.bins.clone(),
+            lib: options.lib,
+        }
+    };
+
+    determine_targets(pkg, &selection)
 }
 
-fn determine_target(pkg: &Package) -> Result<&Target, Error> {
+/// Find every binary and `cdylib` target that should be compiled and
+/// published as part of this package.
+fn determine_targets<'pkg>(
+    pkg: &'pkg Package,
+    selection: &TargetSelection,
+) -> Result<Vec<&'pkg Target>, Error> {
     let candidates: Vec<_> = pkg
         .targets
         .iter()
         .filter(|t| is_webassembly_library(t) || is_binary(t))
         .collect();
-    match *candidates.as_slice() {
-        [single_target] => Ok(single_target),
-        [] => anyhow::bail!(
-            "The {} package doesn't contain any binaries or \"cdylib\" libraries",
+
+    anyhow::ensure!(
+        !candidates.is_empty(),
+        "The {} package doesn't contain any binaries or \"cdylib\" libraries",
+        pkg.name
+    );
+
+    if selection.is_empty() {
+        return Ok(candidates);
+    }
+
+    for name in &selection.bins {
+        anyhow::ensure!(
+            candidates.iter().any(|t| is_binary(t) && &t.name == name),
+            "The {} package doesn't contain a binary called \"{}\"",
+            pkg.name,
+            name
+        );
+    }
+
+    if selection.lib {
+        anyhow::ensure!(
+            candidates.iter().any(|t| is_webassembly_library(t)),
+            "The {} package doesn't contain a \"cdylib\" library",
             pkg.name
-        ),
-        [..] => anyhow::bail!(
-            "Unable to decide what to publish. Expected one executable or \"cdylib\" library, but found {}",
-            candidates.iter()
-                .map(|t| format!("{} ({:?})", t.name, t.kind))
-                .collect::<Vec<_>>()
-                .join(", ")
-        ),
+        );
     }
+
+    Ok(candidates
+        .into_iter()
+        .filter(|t| {
+            if is_binary(t) {
+                selection.bins.contains(&t.name)
+            } else {
+                selection.lib
+            }
+        })
+        .collect())
 }
 
 #[tracing::instrument(skip_all)]
-fn upload_to_wapm(dir: &Path, dry_run: bool) -> Result<(), Error> {
-    let mut cmd = Command::new("wapm");
+fn upload_to_wapm(
+    dir: &Path,
+    manifest: &Manifest,
+    registry: &dyn Registry,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let archive = crate::archive::create_archive(dir)?;
 
-    cmd.arg("publish");
     if dry_run {
-        cmd.arg("--dry-run");
+        tracing::info!(
+            bytes = archive.len(),
+            "Skipping the upload because this is a dry run"
+        );
+        return Ok(());
     }
 
-    cmd.current_dir(dir);
-
-    tracing::debug!(?cmd, "Publishing with the wapm CLI");
+    let upload = Upload {
+        manifest: with_packaged_fs(manifest),
+        readme: read_packaged_file(dir, manifest.package.readme.as_deref())?,
+        license_file: read_packaged_file(dir, manifest.package.license_file.as_deref())?,
+        archive,
+    };
 
-    let status = cmd.status().with_context(|| {
-        format!(
-            "Unable to start \"{}\". Is it installed?",
-            cmd.get_program().to_string_lossy()
-        )
-    })?;
+    registry.publish(&upload)
+}
 
-    if !status.success() {
-        match status.code() {
-            Some(code) => {
-                anyhow::bail!("The wapm CLI exited unsuccessfully with exit code {}", code)
-            }
-            None => anyhow::bail!("The wapm CLI exited unsuccessfully"),
+fn read_packaged_file(dir: &Path, path: Option<&Path>) -> Result<Option<String>, Error> {
+    match path {
+        Some(path) => {
+            let path = dir.join(path);
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read \"{}\"", path.display()))
+                .map(Some)
         }
+        None => Ok(None),
     }
-
-    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
-fn pack(dir: &Path, manifest: &Manifest, wasm_path: &Path, pkg: &Package) -> Result<(), Error> {
+fn pack(
+    dir: &Path,
+    manifest: &Manifest,
+    wasm_paths: &[PathBuf],
+    pkg: &Package,
+) -> Result<(), Error> {
+    if dir.exists() {
+        tracing::debug!(dir = %dir.display(), "Removing files from a previous run");
+        std::fs::remove_dir_all(dir)
+            .with_context(|| format!("Unable to remove the \"{}\" directory", dir.display()))?;
+    }
+
     std::fs::create_dir_all(dir)
         .with_context(|| format!("Unable to create the \"{}\" directory", dir.display()))?;
 
     let manifest_path = dir.join("wapm.toml");
-    let toml = toml::to_string(manifest).context("Unable to serialize the wapm.toml")?;
+    let toml = toml::to_string(&with_packaged_fs(manifest))
+        .context("Unable to serialize the wapm.toml")?;
     tracing::debug!(
         path = %manifest_path.display(),
         bytes = toml.len(),
@@ -161,8 +828,10 @@ fn pack(dir: &Path, manifest: &Manifest, wasm_path: &Path, pkg: &Package) -> Res
     std::fs::write(&manifest_path, toml.as_bytes())
         .with_context(|| format!("Unable to write to \"{}\"", manifest_path.display()))?;
 
-    let new_wasm_path = dir.join(wasm_path.file_name().unwrap());
-    copy(wasm_path, new_wasm_path)?;
+    let modules = manifest.module.as_deref().unwrap_or_default();
+    for (module, wasm_path) in modules.iter().zip(wasm_paths) {
+        copy(wasm_path, dir.join(&module.source))?;
+    }
 
     let base_dir = pkg.manifest_path.parent().unwrap();
 
@@ -180,23 +849,124 @@ fn pack(dir: &Path, manifest: &Manifest, wasm_path: &Path, pkg: &Package) -> Res
 
     for module in manifest.module.as_deref().unwrap_or_default() {
         if let Some(bindings) = &module.bindings {
-            let base_dir = base_dir.as_std_path();
-            for path in bindings.referenced_files(base_dir)? {
-                // Note: we want to maintain the same location relative to the
-                // Cargo.toml file
-                let relative_path = path.strip_prefix(base_dir).with_context(|| {
-                    format!(
-                        "\"{}\" should be inside \"{}\"",
-                        path.display(),
-                        base_dir.display(),
-                    )
-                })?;
-                let dest = dir.join(relative_path);
-                copy(path, dest)?;
+            for (path, relative_path) in bindings_files(bindings, base_dir.as_std_path())? {
+                copy(path, dir.join(relative_path))?;
             }
         }
     }
 
+    for (guest_path, host_path) in manifest.fs.iter().flatten() {
+        validate_fs_path(host_path, base_dir.as_std_path())?;
+        copy_dir(
+            &base_dir.as_std_path().join(host_path),
+            &dir.join(packaged_fs_path(guest_path, host_path)),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Find the files referenced by some bindings, returning each file's full
+/// path and its path relative to the crate's directory.
+pub(crate) fn bindings_files(
+    bindings: &wapm_toml::Bindings,
+    base_dir: &Path,
+) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let mut files = Vec::new();
+
+    for path in bindings.referenced_files(base_dir)? {
+        // Note: we want to maintain the same location relative to the
+        // Cargo.toml file
+        let relative_path = path
+            .strip_prefix(base_dir)
+            .with_context(|| {
+                format!(
+                    "\"{}\" should be inside \"{}\"",
+                    path.display(),
+                    base_dir.display(),
+                )
+            })?
+            .to_path_buf();
+        files.push((path, relative_path));
+    }
+
+    Ok(files)
+}
+
+/// Make sure a directory from the `[fs]` table can be copied into the
+/// package.
+pub(crate) fn validate_fs_path(host_path: &Path, base_dir: &Path) -> Result<(), Error> {
+    // Like bindings, relative directories keep the same location relative
+    // to the Cargo.toml file, so they can't point outside the crate
+    anyhow::ensure!(
+        host_path.is_absolute()
+            || !host_path
+                .components()
+                .any(|c| c == std::path::Component::ParentDir),
+        "The \"{}\" directory in the [fs] table should be an absolute path or a relative path inside \"{}\"",
+        host_path.display(),
+        base_dir.display(),
+    );
+    let full_path = base_dir.join(host_path);
+    anyhow::ensure!(
+        full_path.is_dir(),
+        "The \"{}\" directory in the [fs] table doesn't exist",
+        full_path.display(),
+    );
+
+    Ok(())
+}
+
+/// Where a directory from the `[fs]` table is copied to inside the package.
+///
+/// Absolute paths on the host can't be used inside the package, so they are
+/// moved to `fs/`, named after the directory they are mapped to.
+fn packaged_fs_path(guest_path: &str, host_path: &Path) -> PathBuf {
+    if host_path.is_relative() {
+        return host_path.to_path_buf();
+    }
+
+    let guest: PathBuf = Path::new(guest_path)
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    Path::new("fs").join(guest)
+}
+
+/// The manifest that gets packaged, with every `[fs]` directory pointing at
+/// its location inside the package.
+fn with_packaged_fs(manifest: &Manifest) -> Manifest {
+    let mut manifest = manifest.clone();
+
+    if let Some(fs) = &mut manifest.fs {
+        for (guest_path, host_path) in fs.iter_mut() {
+            *host_path = packaged_fs_path(guest_path, host_path);
+        }
+    }
+
+    manifest
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(to)
+        .with_context(|| format!("Unable to create the \"{}\" directory", to.display()))?;
+
+    let entries = std::fs::read_dir(from)
+        .with_context(|| format!("Unable to read the \"{}\" directory", from.display()))?;
+
+    for entry in entries {
+        let entry = entry
+            .with_context(|| format!("Unable to read the \"{}\" directory", from.display()))?;
+        let path = entry.path();
+        let dest = to.join(entry.file_name());
+
+        if path.is_dir() {
+            copy_dir(&path, &dest)?;
+        } else {
+            copy(&path, &dest)?;
+        }
+    }
+
     Ok(())
 }
 
@@ -209,6 +979,12 @@ fn copy(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), Error> {
         to = %to.display(),
         "Copying file",
     );
+
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create the \"{}\" directory", parent.display()))?;
+    }
+
     std::fs::copy(from, to).with_context(|| {
         format!(
             "Unable to copy \"{}\" to \"{}\"",
@@ -220,29 +996,52 @@ fn copy(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Compile all the `targets` in one go, returning the path to each target's
+/// `*.wasm` file in the same order.
 fn compile_to_wasm(
     pkg: &Package,
     target_dir: &Path,
-    debug: bool,
-    module: &Module,
-    target: &Target,
-) -> Result<PathBuf, Error> {
+    target_triple: &str,
+    targets: &[&Target],
+    features: &[String],
+    options: &BuildOptions,
+) -> Result<Vec<PathBuf>, Error> {
     let mut cmd = Command::new(cargo_bin());
-    let target_triple = match module.abi {
-        wapm_toml::Abi::Emscripten => "wasm32-unknown-emscripten",
-        wapm_toml::Abi::Wasi => "wasm32-wasi",
-        wapm_toml::Abi::None | wapm_toml::Abi::WASM4 => "wasm32-unknown-unknown",
-    };
+    let profile = options.profile();
 
     cmd.arg("build")
         .arg("--quiet")
         .args(["--manifest-path", pkg.manifest_path.as_str()])
-        .args(["--target", target_triple]);
+        .args(["--target", target_triple])
+        .args(["--profile", profile])
+        .arg("--target-dir")
+        .arg(target_dir)
+        .args(options.lockfile_flags());
+
+    for flag in &options.unstable_flags {
+        cmd.args(["-Z", flag]);
+    }
+
+    if !features.is_empty() {
+        cmd.args(["--features", &features.join(",")]);
+    }
+    if options.all_features {
+        cmd.arg("--all-features");
+    }
+    if options.no_default_features {
+        cmd.arg("--no-default-features");
+    }
 
-    if !debug {
-        cmd.arg("--release");
+    for target in targets {
+        if is_binary(target) {
+            cmd.args(["--bin", target.name.as_str()]);
+        } else {
+            cmd.arg("--lib");
+        }
     }
 
+    cmd.args(&options.cargo_args);
+
     tracing::debug!(?cmd, "Compiling the WebAssembly package");
 
     let status = cmd.status().with_context(|| {
@@ -259,19 +1058,117 @@ fn compile_to_wasm(
         }
     }
 
-    let binary = target_dir
-        .join(target_triple)
-        .join(if debug { "debug" } else { "release" })
-        .join(wasm_binary_name(target))
-        .with_extension("wasm");
+    let output_dir = target_dir
+        .join(target_name(target_triple))
+        .join(profile_dir(profile));
 
-    anyhow::ensure!(
-        binary.exists(),
-        "Expected \"{}\" to exist",
-        binary.display()
-    );
+    let mut binaries = Vec::new();
 
-    Ok(binary)
+    for target in targets {
+        let binary = output_dir
+            .join(wasm_binary_name(target))
+            .with_extension("wasm");
+
+        anyhow::ensure!(
+            binary.exists(),
+            "Expected \"{}\" to exist",
+            binary.display()
+        );
+
+        binaries.push(binary);
+    }
+
+    Ok(binaries)
+}
+
+/// The WASI target triples, from newest to oldest.
+///
+/// Rust 1.78 renamed `wasm32-wasi` to `wasm32-wasip1`, and newer toolchains
+/// have removed the old name altogether.
+const WASI_TARGETS: &[&str] = &["wasm32-wasip1", "wasm32-wasi"];
+
+fn default_target(abi: wapm_toml::Abi, base_dir: &Path) -> &'static str {
+    match abi {
+        wapm_toml::Abi::Emscripten => "wasm32-unknown-emscripten",
+        wapm_toml::Abi::Wasi => wasi_target(base_dir),
+        wapm_toml::Abi::None | wapm_toml::Abi::WASM4 => "wasm32-unknown-unknown",
+    }
+}
+
+/// Find the WASI target triple the toolchain has a standard library for.
+///
+/// This runs `rustc` from the crate's directory so any `rust-toolchain` file
+/// is respected, and the answer is cached because it won't change while
+/// `cargo wapm` is running.
+fn wasi_target(base_dir: &Path) -> &'static str {
+    static WASI_TARGET: OnceLock<&'static str> = OnceLock::new();
+
+    WASI_TARGET.get_or_init(|| {
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+        let print = |what: &str| {
+            let output = Command::new(&rustc)
+                .args(["--print", what])
+                .current_dir(base_dir)
+                .output();
+            match output {
+                Ok(output) if output.status.success() => {
+                    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+                }
+                other => {
+                    tracing::debug!(?other, %rustc, what, "Unable to query rustc");
+                    None
+                }
+            }
+        };
+
+        // Prefer whichever target has its standard library installed, then
+        // fall back to whichever target the compiler knows about
+        if let Some(sysroot) = print("sysroot") {
+            let rustlib = Path::new(sysroot.trim()).join("lib").join("rustlib");
+            if let Some(triple) = pick_wasi_target(|triple| rustlib.join(triple).is_dir()) {
+                return triple;
+            }
+        }
+
+        print("target-list")
+            .and_then(|list| pick_wasi_target(|triple| list.lines().any(|l| l.trim() == triple)))
+            .unwrap_or(WASI_TARGETS[0])
+    })
+}
+
+fn pick_wasi_target(is_available: impl Fn(&str) -> bool) -> Option<&'static str> {
+    WASI_TARGETS
+        .iter()
+        .copied()
+        .find(|&triple| is_available(triple))
+}
+
+/// Is this the path to a custom target JSON file rather than a triple?
+pub(crate) fn is_custom_target(target: &str) -> bool {
+    target.ends_with(".json")
+}
+
+/// The name of the directory cargo writes a target's artifacts to, which is
+/// the file name (without extension) for custom target JSON files.
+fn target_name(target_triple: &str) -> &str {
+    if is_custom_target(target_triple) {
+        Path::new(target_triple)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(target_triple)
+    } else {
+        target_triple
+    }
+}
+
+/// The directory inside `target/<triple>/` that a profile's artifacts are
+/// written to.
+fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        other => other,
+    }
 }
 
 fn wasm_binary_name(target: &Target) -> String {
@@ -288,59 +1185,87 @@ fn cargo_bin() -> String {
     std::env::var("CARGO").unwrap_or_else(|_| String::from("cargo"))
 }
 
-fn is_webassembly_library(target: &Target) -> bool {
+pub(crate) fn is_webassembly_library(target: &Target) -> bool {
     target.kind.iter().any(|k| k == "cdylib")
 }
 
-fn is_binary(target: &Target) -> bool {
+/// Does this package have its own `[package.metadata.wapm]` table?
+pub(crate) fn has_wapm_table(pkg: &Package) -> bool {
+    matches!(pkg.metadata.get("wapm"), Some(serde_json::Value::Object(_)))
+}
+
+pub(crate) fn is_binary(target: &Target) -> bool {
     target.kind.iter().any(|k| k == "bin")
 }
 
 #[tracing::instrument(skip_all)]
-fn generate_manifest(pkg: &Package, target: &Target) -> Result<Manifest, Error> {
-    tracing::trace!(?target, "The target");
-
-    let MetadataTable {
-        wapm:
-            Wapm {
-                wasmer_extra_flags,
-                fs,
-                abi,
-                namespace,
-                package,
-                bindings,
-            },
-    } = MetadataTable::deserialize(&pkg.metadata)
-        .context("Unable to deserialize the [metadata] table")?;
-
-    match pkg.description.as_deref() {
-        Some("") => anyhow::bail!("The \"description\" field in your Cargo.toml is empty"),
-        Some(_) => {}
-        None => anyhow::bail!("The \"description\" field in your Cargo.toml wasn't set"),
-    }
-
-    let package_name = format!("{}/{}", namespace, package.as_deref().unwrap_or(&pkg.name));
+pub(crate) fn generate_manifest(
+    pkg: &Package,
+    wapm: Wapm,
+    targets: &[&Target],
+) -> Result<Manifest, Error> {
+    tracing::trace!(?targets, "The targets");
 
-    let module = Module {
-        name: target.name.clone(),
-        source: PathBuf::from(wasm_binary_name(target)).with_extension("wasm"),
+    let Wapm {
+        wasmer_extra_flags,
+        fs,
         abi,
+        target: _,
+        namespace,
+        package,
         bindings,
-        interfaces: None,
-        kind: None,
-    };
+        bins: _,
+        lib: _,
+        features: _,
+        optimize: _,
+        strip: _,
+        commands,
+        dependencies,
+        runtime_dependencies: _,
+    } = wapm;
+
+    let package_name = wapm_package_name(&namespace, package.as_deref(), pkg);
 
-    let command = if is_binary(target) {
-        let cmd = wapm_toml::Command::V1(wapm_toml::CommandV1 {
-            module: target.name.clone(),
+    // Bindings describe the interface exported by a library, so we only
+    // attach them to binaries when there is no library to attach them to.
+    let has_library = targets.iter().any(|t| is_webassembly_library(t));
+
+    let mut modules: Vec<Module> = Vec::new();
+
+    for &target in targets {
+        let module = Module {
             name: target.name.clone(),
-            package: Some(package_name.clone()),
-            main_args: None,
-        });
-        Some(vec![cmd])
-    } else {
-        None
-    };
+            source: PathBuf::from(wasm_binary_name(target)).with_extension("wasm"),
+            abi,
+            bindings: if is_webassembly_library(target) || !has_library {
+                bindings.clone()
+            } else {
+                None
+            },
+            interfaces: None,
+            kind: None,
+        };
+
+        if let Some(existing) = modules
+            .iter()
+            .find(|m| m.name == module.name || m.source == module.source)
+        {
+            anyhow::bail!(
+                "Unable to publish both \"{}\" and \"{}\" because they would be packaged as the same module",
+                existing.name,
+                module.name,
+            );
+        }
+
+        modules.push(module);
+    }
+
+    let commands = generate_commands(
+        &package_name,
+        &modules,
+        targets,
+        commands.unwrap_or_default(),
+    )?;
 
     Ok(Manifest {
         package: wapm_toml::Package {
@@ -348,24 +1273,313 @@ fn generate_manifest(pkg: &Package, target: &Target) -> Result<Manifest, Error>
             version: pkg.version.clone(),
             description: pkg.description.clone().unwrap_or_default(),
             license: pkg.license.clone(),
-            license_file: pkg.license_file().map(|p| p.into_std_path_buf()),
-            readme: pkg.readme().map(|p| p.into_std_path_buf()),
+            // Note: pack() copies these files into the top-level directory
+            license_file: pkg
+                .license_file
+                .as_ref()
+                .and_then(|p| p.file_name())
+                .map(PathBuf::from),
+            readme: pkg
+                .readme
+                .as_ref()
+                .and_then(|p| p.file_name())
+                .map(PathBuf::from),
             repository: pkg.repository.clone(),
             homepage: pkg.homepage.clone(),
             wasmer_extra_flags,
             disable_command_rename: false,
             rename_commands_to_raw_command_name: false,
         },
-        module: Some(vec![module]),
-        command,
+        module: Some(modules),
+        command: if commands.is_empty() {
+            None
+        } else {
+            Some(commands)
+        },
         fs,
-        dependencies: None,
+        dependencies,
         base_directory_path: PathBuf::new(),
     })
 }
 
+pub(crate) fn validate_description(pkg: &Package) -> Result<(), Error> {
+    match pkg.description.as_deref() {
+        Some("") => anyhow::bail!("The \"description\" field in your Cargo.toml is empty"),
+        Some(_) => Ok(()),
+        None => anyhow::bail!("The \"description\" field in your Cargo.toml wasn't set"),
+    }
+}
+
+/// The fully qualified name a package will be published as.
+/// Warn about a namespace or package name the registry may reject, alongside
+/// the `Cargo.toml` key it came from.
+pub(crate) fn name_warnings(pkg: &Package, wapm: &Wapm) -> Vec<(&'static str, String)> {
+    let names = [
+        (
+            "package.metadata.wapm.namespace",
+            "namespace",
+            wapm.namespace.as_str(),
+        ),
+        match &wapm.package {
+            Some(package) => ("package.metadata.wapm.package", "package name", package),
+            None => ("package.name", "package name", pkg.name.as_str()),
+        },
+    ];
+
+    names
+        .into_iter()
+        .filter_map(|(key, kind, name)| {
+            crate::metadata::name_warning(kind, name).map(|warning| (key, warning))
+        })
+        .collect()
+}
+
+fn wapm_package_name(namespace: &str, package: Option<&str>, pkg: &Package) -> String {
+    format!("{}/{}", namespace, package.unwrap_or(&pkg.name))
+}
+
+/// Find the names and versions that each of the `runtime_dependencies` will be
+/// published to WAPM with, taking any namespace or version overrides into
+/// account.
+pub(crate) fn workspace_dependencies(
+    metadata: &Metadata,
+    pkg: &Package,
+    runtime_dependencies: &[String],
+    options: &BuildOptions,
+) -> Result<HashMap<String, String>, Error> {
+    let mut dependencies = HashMap::new();
+
+    for name in runtime_dependencies {
+        let dep = pkg
+            .dependencies
+            .iter()
+            .find(|d| &d.name == name && d.path.is_some())
+            .with_context(|| {
+                format!(
+                    "\"{}\" is a runtime dependency, but it isn't a path dependency of {}",
+                    name, pkg.name
+                )
+            })?;
+        let dep_dir = dep.path.as_deref();
+
+        let sibling = metadata
+            .packages
+            .iter()
+            .find(|p| p.name == dep.name && p.manifest_path.parent() == dep_dir)
+            .with_context(|| format!("Unable to find the \"{}\" package", name))?;
+
+        // Only crates with their own table get published, so don't let the
+        // workspace defaults make up for a missing one
+        anyhow::ensure!(
+            has_wapm_table(sibling),
+            "\"{}\" is a runtime dependency, but it doesn't have a [package.metadata.wapm] table",
+            name
+        );
+
+        let MetadataTable { wapm } = MetadataTable::for_package(sibling, metadata)
+            .with_context(|| {
+                format!(
+                    "\"{}\" is a runtime dependency, but it doesn't have a valid [package.metadata.wapm] table",
+                    name
+                )
+            })?;
+
+        let namespace = options.namespace.as_deref().unwrap_or(&wapm.namespace);
+        let version = options.sibling_version(sibling);
+        let package_name = wapm_package_name(namespace, wapm.package.as_deref(), sibling);
+        tracing::debug!(
+            dependency = %package_name,
+            %version,
+            "Found a runtime dependency in the workspace",
+        );
+        dependencies.insert(package_name, format!("^{}", version));
+    }
+
+    Ok(dependencies)
+}
+
+pub(crate) fn validate_dependencies(dependencies: &HashMap<String, String>) -> Result<(), Error> {
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+
+    for name in names {
+        let is_qualified = match name.split_once('/') {
+            Some((namespace, package)) => {
+                !namespace.is_empty() && !package.is_empty() && !package.contains('/')
+            }
+            None => false,
+        };
+        anyhow::ensure!(
+            is_qualified,
+            "The \"{}\" dependency should be in the form \"namespace/name\"",
+            name
+        );
+
+        let version = &dependencies[name];
+        VersionReq::parse(version).with_context(|| {
+            format!(
+                "The \"{}\" dependency has an invalid version requirement, \"{}\"",
+                name, version
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Create a command for every explicitly configured command, plus a default
+/// command for each binary that isn't already exposed by one.
+fn generate_commands(
+    package_name: &str,
+    modules: &[Module],
+    targets: &[&Target],
+    config: BTreeMap<String, CommandConfig>,
+) -> Result<Vec<wapm_toml::Command>, Error> {
+    let mut commands = Vec::new();
+
+    for target in targets.iter().filter(|t| is_binary(t)) {
+        let has_explicit_command = config.contains_key(&target.name)
+            || config
+                .values()
+                .any(|c| c.module.as_deref() == Some(target.name.as_str()));
+
+        if !has_explicit_command {
+            commands.push(wapm_toml::Command::V1(wapm_toml::CommandV1 {
+                module: target.name.clone(),
+                name: target.name.clone(),
+                package: Some(package_name.to_string()),
+                main_args: None,
+            }));
+        }
+    }
+
+    for (name, cmd) in config {
+        let CommandConfig {
+            module,
+            main_args,
+            runner,
+            annotations,
+        } = cmd;
+        let module_name = module.unwrap_or_else(|| name.clone());
+
+        let module = modules
+            .iter()
+            .find(|m| m.name == module_name)
+            .with_context(|| {
+                format!(
+                    "The \"{}\" command uses the \"{}\" module, but it isn't being published",
+                    name, module_name
+                )
+            })?;
+
+        let command = match (runner, annotations) {
+            (None, None) => wapm_toml::Command::V1(wapm_toml::CommandV1 {
+                module: module_name,
+                name,
+                package: Some(package_name.to_string()),
+                main_args,
+            }),
+            (runner, mut annotations) => {
+                // Runners and annotations are only understood by the newer
+                // command format, which passes "main args" to the runner as
+                // an annotation
+                if let Some(main_args) = &main_args {
+                    annotations =
+                        Some(with_main_args(annotations, main_args).with_context(|| {
+                            format!("Unable to add \"main-args\" to the \"{}\" command", name)
+                        })?);
+                }
+
+                let runner = match runner {
+                    Some(runner) => runner_uri(&runner).with_context(|| {
+                        format!("Invalid runner for the \"{}\" command", name)
+                    })?,
+                    None => default_runner(module.abi)
+                        .with_context(|| {
+                            format!(
+                                "Unable to determine which runner the \"{}\" command should use with the \"{}\" ABI",
+                                name, module.abi
+                            )
+                        })?
+                        .to_string(),
+                };
+
+                let annotations = annotations
+                    .map(toml::Value::try_from)
+                    .transpose()
+                    .with_context(|| format!("Invalid annotations for the \"{}\" command", name))?;
+
+                wapm_toml::Command::V2(wapm_toml::CommandV2 {
+                    name,
+                    module: module_name,
+                    runner,
+                    annotations: annotations.map(CommandAnnotations::Raw),
+                })
+            }
+        };
+
+        commands.push(command);
+    }
+
+    Ok(commands)
+}
+
+/// Add `main-args` to a V2 command's `wasi` annotations.
+///
+/// Like the Wasmer runtime does when upgrading V1 commands, the arguments are
+/// split on whitespace.
+fn with_main_args(
+    annotations: Option<serde_json::Value>,
+    main_args: &str,
+) -> Result<serde_json::Value, Error> {
+    let mut annotations = annotations.unwrap_or_else(|| serde_json::json!({}));
+
+    let wasi = annotations
+        .as_object_mut()
+        .context("The annotations should be a table")?
+        .entry("wasi")
+        .or_insert_with(|| serde_json::json!({}))
+        .as_object_mut()
+        .context("The \"wasi\" annotation should be a table")?;
+    anyhow::ensure!(
+        !wasi.contains_key("main-args"),
+        "\"main-args\" is already set in the \"wasi\" annotations"
+    );
+
+    let args: Vec<_> = main_args.split_whitespace().collect();
+    wasi.insert("main-args".to_string(), serde_json::json!(args));
+
+    Ok(annotations)
+}
+
+const WASI_RUNNER: &str = "https://webc.org/runner/wasi";
+const WCGI_RUNNER: &str = "https://webc.org/runner/wcgi";
+const EMSCRIPTEN_RUNNER: &str = "https://webc.org/runner/emscripten";
+
+fn default_runner(abi: wapm_toml::Abi) -> Option<&'static str> {
+    match abi {
+        wapm_toml::Abi::Wasi => Some(WASI_RUNNER),
+        wapm_toml::Abi::Emscripten => Some(EMSCRIPTEN_RUNNER),
+        wapm_toml::Abi::None | wapm_toml::Abi::WASM4 => None,
+    }
+}
+
+/// Expand a runner's short name into its full URI.
+fn runner_uri(runner: &str) -> Result<String, Error> {
+    match runner {
+        "wasi" => Ok(WASI_RUNNER.to_string()),
+        "wcgi" => Ok(WCGI_RUNNER.to_string()),
+        "emscripten" => Ok(EMSCRIPTEN_RUNNER.to_string()),
+        uri if uri.contains("://") => Ok(uri.to_string()),
+        other => anyhow::bail!(
+            "Unknown runner, \"{}\". Expected \"wasi\", \"wcgi\", \"emscripten\", or a URI",
+            other
+        ),
+    }
+}
+
 #[tracing::instrument(skip_all)]
-fn determine_crates_to_publish<'meta>(
+pub(crate) fn determine_crates_to_publish<'meta>(
     metadata: &'meta Metadata,
     workspace: bool,
     current_dir: &Path,
@@ -392,12 +1606,7 @@ fn determine_crates_to_publish<'meta>(
                 continue;
             }
 
-            if pkg
-                .metadata
-                .as_object()
-                .and_then(|m| m.get("wapm"))
-                .is_none()
-            {
+            if !has_wapm_table(pkg) {
                 tracing::debug!(
                     "Skipping because it doesn't contain a [package.metadata.wapm] table"
                 );
@@ -431,3 +1640,858 @@ fn determine_crates_to_publish<'meta>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cargo_metadata::semver::Version;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::fixtures::{package_json, target, workspace};
+
+    fn package(targets: Vec<serde_json::Value>) -> Package {
+        package_with_metadata(
+            targets,
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+            }),
+        )
+    }
+
+    fn package_with_metadata(targets: Vec<serde_json::Value>, wapm: serde_json::Value) -> Package {
+        serde_json::from_value(package_json("my-tool", targets, wapm, Vec::new())).unwrap()
+    }
+
+    fn wapm(pkg: &Package) -> Wapm {
+        MetadataTable::deserialize(&pkg.metadata).unwrap().wapm
+    }
+
+    #[test]
+    fn generate_a_module_and_command_per_binary() {
+        let pkg = package(vec![
+            target("my-tool", "bin"),
+            target("my-tool-server", "bin"),
+            target("my_tool_lib", "cdylib"),
+            target("integration", "test"),
+        ]);
+
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let manifest = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap();
+
+        let modules: Vec<_> = manifest
+            .module
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.name, m.source))
+            .collect();
+        assert_eq!(
+            modules,
+            vec![
+                ("my-tool".to_string(), PathBuf::from("my-tool.wasm")),
+                (
+                    "my-tool-server".to_string(),
+                    PathBuf::from("my-tool-server.wasm")
+                ),
+                ("my_tool_lib".to_string(), PathBuf::from("my_tool_lib.wasm")),
+            ]
+        );
+        let commands: Vec<_> = manifest
+            .command
+            .unwrap()
+            .iter()
+            .map(|c| c.get_name())
+            .collect();
+        assert_eq!(commands, vec!["my-tool", "my-tool-server"]);
+    }
+
+    #[test]
+    fn configure_commands_from_metadata() {
+        let pkg = package_with_metadata(
+            vec![target("mytool-cli", "bin"), target("helper", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "commands": {
+                    "mytool": {
+                        "module": "mytool-cli",
+                        "main-args": "--color=always",
+                    },
+                    "serve": {
+                        "module": "mytool-cli",
+                        "main-args": "serve --port 8080",
+                        "annotations": { "wasi": { "env": ["PORT=8080"] } },
+                    },
+                },
+            }),
+        );
+
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let manifest = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap();
+
+        toml::to_string(&manifest).expect("The manifest should be serializable");
+        let commands = manifest.command.unwrap();
+        let names: Vec<_> = commands.iter().map(|c| c.get_name()).collect();
+        assert_eq!(names, vec!["helper", "mytool", "serve"]);
+        assert_eq!(commands[1].get_module(), "mytool-cli");
+        assert_eq!(
+            commands[1].get_main_args().as_deref(),
+            Some("--color=always")
+        );
+        match &commands[2] {
+            wapm_toml::Command::V2(cmd) => {
+                assert_eq!(cmd.module, "mytool-cli");
+                assert_eq!(cmd.runner, "https://webc.org/runner/wasi");
+                assert_eq!(
+                    cmd.annotations,
+                    Some(CommandAnnotations::Raw(toml::toml! {
+                        [wasi]
+                        env = ["PORT=8080"]
+                        main-args = ["serve", "--port", "8080"]
+                    }))
+                );
+            }
+            other => panic!("Expected a V2 command, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn main_args_cant_be_set_twice() {
+        let annotations = json!({ "wasi": { "main-args": ["--verbose"] } });
+
+        let err = with_main_args(Some(annotations), "--quiet").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "\"main-args\" is already set in the \"wasi\" annotations"
+        );
+    }
+
+    #[test]
+    fn commands_with_a_runner_use_the_v2_format() {
+        let pkg = package_with_metadata(
+            vec![target("server", "bin"), target("cli", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "commands": {
+                    "server": { "runner": "wcgi" },
+                    "cli": { "runner": "https://example.com/runner" },
+                },
+            }),
+        );
+
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let manifest = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap();
+
+        let runners: Vec<_> = manifest
+            .command
+            .unwrap()
+            .into_iter()
+            .map(|cmd| match cmd {
+                wapm_toml::Command::V2(v2) => (v2.name, v2.runner),
+                other => panic!("Expected a V2 command, found {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            runners,
+            vec![
+                ("cli".to_string(), "https://example.com/runner".to_string()),
+                ("server".to_string(), WCGI_RUNNER.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_runner() {
+        assert!(runner_uri("wasix-but-misspelled").is_err());
+    }
+
+    #[test]
+    fn commands_must_use_a_published_module() {
+        let pkg = package_with_metadata(
+            vec![target("mytool", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "commands": { "other": {} },
+            }),
+        );
+
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let err = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap_err();
+
+        assert!(err.to_string().contains("\"other\" module"));
+    }
+
+    #[test]
+    fn copy_dependencies_into_the_manifest() {
+        let pkg = package_with_metadata(
+            vec![target("my-tool", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "dependencies": { "wasmer/python": "^3.12" },
+            }),
+        );
+
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let manifest = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap();
+
+        let dependencies = manifest.dependencies.unwrap();
+        assert_eq!(dependencies["wasmer/python"], "^3.12");
+    }
+
+    #[test]
+    fn declare_workspace_siblings_as_dependencies() {
+        let metadata = workspace(vec![
+            package_json(
+                "my-tool",
+                vec![target("my-tool", "bin")],
+                json!({
+                    "namespace": "wasmer",
+                    "abi": "wasi",
+                    "runtime-dependencies": ["my-plugin"],
+                }),
+                vec!["my-plugin", "my-utils"],
+            ),
+            package_json(
+                "my-plugin",
+                vec![target("my_plugin", "cdylib")],
+                json!({
+                    "namespace": "plugins",
+                    "package": "plugin",
+                    "abi": "none",
+                }),
+                Vec::new(),
+            ),
+            package_json(
+                "my-utils",
+                vec![target("my_utils", "lib")],
+                json!(null),
+                Vec::new(),
+            ),
+        ]);
+        let pkg = &metadata.packages[0];
+
+        let got = workspace_dependencies(
+            &metadata,
+            pkg,
+            &["my-plugin".to_string()],
+            &BuildOptions::default(),
+        )
+        .unwrap();
+
+        let mut should_be = HashMap::new();
+        should_be.insert("plugins/plugin".to_string(), "^1.2.3".to_string());
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn output_directory_depends_on_the_profile() {
+        let inputs = [
+            (BuildOptions::default(), "release"),
+            (
+                BuildOptions {
+                    debug: true,
+                    ..Default::default()
+                },
+                "debug",
+            ),
+            (
+                BuildOptions {
+                    profile: Some("wasm-release".to_string()),
+                    ..Default::default()
+                },
+                "wasm-release",
+            ),
+            (
+                BuildOptions {
+                    profile: Some("test".to_string()),
+                    ..Default::default()
+                },
+                "debug",
+            ),
+        ];
+
+        for (options, dir) in inputs {
+            assert_eq!(profile_dir(options.profile()), dir);
+        }
+    }
+
+    #[test]
+    fn combine_features_from_metadata_and_the_command_line() {
+        let pkg = package_with_metadata(
+            vec![target("my-tool", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "features": ["wasm", "cli"],
+            }),
+        );
+        let options = BuildOptions {
+            features: Some(Features::from("cli,extra")),
+            ..Default::default()
+        };
+
+        let features = options.features_for(&wapm(&pkg));
+
+        assert_eq!(features, vec!["wasm", "cli", "extra"]);
+    }
+
+    #[test]
+    fn pick_the_target_triple() {
+        let custom = package_with_metadata(
+            vec![target("my-tool", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "target": "targets/my-target.json",
+            }),
+        );
+        let threads = package_with_metadata(
+            vec![target("my-tool", "bin")],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "target": "wasm32-wasip1-threads",
+            }),
+        );
+        let base_dir = Path::new("/path/to/my-tool");
+        let overridden = BuildOptions {
+            target: Some("wasm32-wasip2".to_string()),
+            ..Default::default()
+        };
+
+        let triple = BuildOptions::default().target_triple(&wapm(&custom), base_dir);
+        assert_eq!(
+            triple,
+            base_dir
+                .join("targets/my-target.json")
+                .display()
+                .to_string()
+        );
+        assert_eq!(target_name(&triple), "my-target");
+        assert_eq!(
+            BuildOptions::default().target_triple(&wapm(&threads), base_dir),
+            "wasm32-wasip1-threads"
+        );
+        assert_eq!(
+            overridden.target_triple(&wapm(&custom), base_dir),
+            "wasm32-wasip2"
+        );
+        assert_eq!(target_name("wasm32-wasip2"), "wasm32-wasip2");
+    }
+
+    #[test]
+    fn fall_back_to_the_old_wasi_target() {
+        let new_toolchain = ["wasm32-unknown-unknown", "wasm32-wasip1", "wasm32-wasip2"];
+        let old_toolchain = ["wasm32-unknown-unknown", "wasm32-wasi"];
+
+        assert_eq!(
+            pick_wasi_target(|t| new_toolchain.contains(&t)),
+            Some("wasm32-wasip1")
+        );
+        assert_eq!(
+            pick_wasi_target(|t| old_toolchain.contains(&t)),
+            Some("wasm32-wasi")
+        );
+        assert_eq!(pick_wasi_target(|t| t == "wasm32-unknown-unknown"), None);
+    }
+
+    #[test]
+    fn override_the_version() {
+        let pkg = package(vec![target("my-tool", "bin")]);
+        let suffix = parse_version_suffix("nightly.20230101").unwrap();
+
+        let options = BuildOptions {
+            version_suffix: Some(suffix.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.version_for(&pkg),
+            "1.2.3-nightly.20230101".parse::<Version>().unwrap()
+        );
+
+        let options = BuildOptions {
+            package_version: Some("2.0.0-beta.1".parse().unwrap()),
+            version_suffix: Some(suffix),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.version_for(&pkg),
+            "2.0.0-beta.1.nightly.20230101".parse::<Version>().unwrap()
+        );
+
+        assert!(parse_version_suffix("nightly build").is_err());
+        assert!(parse_version_suffix("").is_err());
+    }
+
+    #[test]
+    fn version_overrides_only_apply_to_a_single_crate() {
+        let metadata = utils_plugin_and_other();
+        let options = BuildOptions {
+            workspace: true,
+            package_version: Some("2.0.0".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let err = options.packages(&metadata).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "The --package-version flag can only be used with a single crate, but 3 were selected"
+        );
+    }
+
+    #[test]
+    fn runtime_dependencies_keep_their_own_version() {
+        let metadata = workspace(vec![
+            package_json(
+                "my-tool",
+                vec![target("my-tool", "bin")],
+                json!({
+                    "namespace": "wasmer",
+                    "abi": "wasi",
+                    "runtime-dependencies": ["my-plugin"],
+                }),
+                vec!["my-plugin"],
+            ),
+            package_json(
+                "my-plugin",
+                vec![target("my_plugin", "cdylib")],
+                json!({ "namespace": "wasmer", "abi": "none" }),
+                Vec::new(),
+            ),
+        ]);
+        let options = BuildOptions {
+            package_version: Some("2.0.0".parse().unwrap()),
+            version_suffix: Some(parse_version_suffix("nightly").unwrap()),
+            ..Default::default()
+        };
+
+        let (manifest, _) = prepare(&metadata.packages[0], &metadata, &options).unwrap();
+
+        assert_eq!(manifest.package.version.to_string(), "2.0.0-nightly");
+        let dependencies = manifest.dependencies.unwrap();
+        assert_eq!(dependencies["wasmer/my-plugin"], "^1.2.3-nightly");
+    }
+
+    #[test]
+    fn override_the_namespace_and_package_name() {
+        let metadata = workspace(vec![
+            package_json(
+                "my-tool",
+                vec![target("my-tool", "bin")],
+                json!({
+                    "namespace": "wasmer",
+                    "abi": "wasi",
+                    "runtime-dependencies": ["my-plugin"],
+                }),
+                vec!["my-plugin"],
+            ),
+            package_json(
+                "my-plugin",
+                vec![target("my_plugin", "cdylib")],
+                json!({
+                    "namespace": "plugins",
+                    "package": "plugin",
+                    "abi": "none",
+                }),
+                Vec::new(),
+            ),
+        ]);
+        let options = BuildOptions {
+            namespace: Some("testing".to_string()),
+            package_name: Some("my-tool-beta".to_string()),
+            ..Default::default()
+        };
+
+        let (manifest, _) = prepare(&metadata.packages[0], &metadata, &options).unwrap();
+
+        assert_eq!(manifest.package.name, "testing/my-tool-beta");
+        let dependencies = manifest.dependencies.unwrap();
+        assert_eq!(dependencies["testing/plugin"], "^1.2.3");
+    }
+
+    #[test]
+    fn runtime_dependencies_need_their_own_metadata() {
+        let metadata = workspace(vec![
+            package_json(
+                "my-tool",
+                vec![target("my-tool", "bin")],
+                json!({ "namespace": "wasmer", "abi": "wasi" }),
+                vec!["my-utils"],
+            ),
+            package_json(
+                "my-utils",
+                vec![target("my_utils", "lib")],
+                json!(null),
+                Vec::new(),
+            ),
+        ]);
+        let pkg = &metadata.packages[0];
+
+        assert!(workspace_dependencies(
+            &metadata,
+            pkg,
+            &["my-utils".to_string()],
+            &BuildOptions::default()
+        )
+        .is_err());
+        assert!(workspace_dependencies(
+            &metadata,
+            pkg,
+            &["unknown".to_string()],
+            &BuildOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn workspace_defaults_dont_count_as_runtime_dependency_metadata() {
+        let mut metadata = workspace(vec![
+            package_json(
+                "my-tool",
+                vec![target("my-tool", "bin")],
+                json!({ "runtime-dependencies": ["my-utils"] }),
+                vec!["my-utils"],
+            ),
+            package_json(
+                "my-utils",
+                vec![target("my_utils", "cdylib")],
+                json!(null),
+                Vec::new(),
+            ),
+        ]);
+        metadata.workspace_metadata = json!({
+            "wapm": { "namespace": "our-org", "abi": "wasi" },
+        });
+        let pkg = &metadata.packages[0];
+
+        let err = workspace_dependencies(
+            &metadata,
+            pkg,
+            &["my-utils".to_string()],
+            &BuildOptions::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "\"my-utils\" is a runtime dependency, but it doesn't have a [package.metadata.wapm] table"
+        );
+    }
+
+    #[test]
+    fn invalid_dependencies() {
+        let inputs = [("python", "^3.12"), ("wasmer/python", "three point twelve")];
+
+        for (name, version) in inputs {
+            let mut dependencies = HashMap::new();
+            dependencies.insert(name.to_string(), version.to_string());
+
+            assert!(
+                validate_dependencies(&dependencies).is_err(),
+                "{} = {}",
+                name,
+                version
+            );
+        }
+    }
+
+    /// A stand-in for the real registry which knows about a fixed set of
+    /// package versions.
+    struct LocalRegistry(Vec<(&'static str, &'static str)>);
+
+    impl Registry for LocalRegistry {
+        fn version_exists(&self, package_name: &str, version: &Version) -> Result<bool, Error> {
+            Ok(self
+                .0
+                .iter()
+                .any(|&(name, v)| name == package_name && v == version.to_string()))
+        }
+
+        fn publish(&self, upload: &Upload) -> Result<(), Error> {
+            anyhow::bail!(
+                "Unable to publish \"{}\" to a local registry",
+                upload.manifest.package.name
+            )
+        }
+    }
+
+    #[test]
+    fn detect_versions_which_were_already_published() {
+        let pkg = package(vec![target("my-tool", "bin")]);
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let manifest = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap();
+        let registry = LocalRegistry(vec![("wasmer/my-tool", "1.2.2"), ("wasmer/other", "1.2.3")]);
+
+        assert!(!is_already_published(&registry, &manifest).unwrap());
+
+        let registry = LocalRegistry(vec![("wasmer/my-tool", "1.2.3")]);
+
+        assert!(is_already_published(&registry, &manifest).unwrap());
+    }
+
+    #[test]
+    fn absolute_fs_paths_are_moved_into_the_package() {
+        let crate_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(crate_dir.path().join("assets")).unwrap();
+        let host_dir = tempfile::tempdir().unwrap();
+        let pkg = package(vec![target("my-tool", "bin")]);
+        let mut wapm = wapm(&pkg);
+        let mut fs = HashMap::new();
+        fs.insert("/assets".to_string(), PathBuf::from("assets"));
+        fs.insert("/data/cache".to_string(), host_dir.path().to_path_buf());
+        wapm.fs = Some(fs);
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let manifest = generate_manifest(&pkg, wapm, &targets).unwrap();
+
+        for host_path in manifest.fs.iter().flat_map(|fs| fs.values()) {
+            validate_fs_path(host_path, crate_dir.path()).unwrap();
+        }
+        assert!(validate_fs_path(Path::new("../assets"), crate_dir.path()).is_err());
+
+        let fs = with_packaged_fs(&manifest).fs.unwrap();
+        assert_eq!(fs["/assets"], Path::new("assets"));
+        assert_eq!(
+            fs["/data/cache"],
+            Path::new("fs").join("data").join("cache")
+        );
+    }
+
+    #[test]
+    fn crate_names_with_underscores_are_used_as_is() {
+        let mut pkg = package(vec![target("my_tool", "bin")]);
+        pkg.name = "my_tool".to_string();
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+
+        let manifest = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap();
+
+        assert_eq!(manifest.package.name, "wasmer/my_tool");
+        assert!(name_warnings(&pkg, &wapm(&pkg)).is_empty());
+    }
+
+    #[test]
+    fn warn_about_unusual_package_names() {
+        let pkg = package(vec![target("my-tool", "bin")]);
+        let mut wapm = wapm(&pkg);
+        wapm.package = Some("my.tool".to_string());
+
+        let warnings = name_warnings(&pkg, &wapm);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, "package.metadata.wapm.package");
+        assert!(warnings[0].1.contains("Try \"my-tool\" instead"));
+    }
+
+    #[test]
+    fn modules_must_have_unique_names() {
+        let pkg = package(vec![target("my-tool", "bin"), target("my-tool", "cdylib")]);
+
+        let targets = determine_targets(&pkg, &TargetSelection::default()).unwrap();
+        let err = generate_manifest(&pkg, wapm(&pkg), &targets).unwrap_err();
+
+        assert!(err.to_string().contains("same module"));
+    }
+
+    #[test]
+    fn nothing_to_publish() {
+        let pkg = package(vec![target("my-tool", "rlib")]);
+
+        assert!(determine_targets(&pkg, &TargetSelection::default()).is_err());
+    }
+
+    #[test]
+    fn select_targets_from_metadata() {
+        let pkg = package_with_metadata(
+            vec![
+                target("helper", "bin"),
+                target("my-tool", "bin"),
+                target("my_tool", "cdylib"),
+            ],
+            json!({
+                "namespace": "wasmer",
+                "abi": "wasi",
+                "bins": ["my-tool"],
+            }),
+        );
+        let selection = TargetSelection::from_metadata(&wapm(&pkg));
+
+        let targets = determine_targets(&pkg, &selection).unwrap();
+
+        let names: Vec<_> = targets.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["my-tool"]);
+    }
+
+    #[test]
+    fn select_only_the_library() {
+        let pkg = package(vec![target("helper", "bin"), target("my_tool", "cdylib")]);
+        let selection = TargetSelection {
+            bins: Vec::new(),
+            lib: true,
+        };
+
+        let targets = determine_targets(&pkg, &selection).unwrap();
+
+        let names: Vec<_> = targets.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["my_tool"]);
+    }
+
+    #[test]
+    fn selecting_a_missing_binary_is_an_error() {
+        let pkg = package(vec![target("my-tool", "bin")]);
+        let selection = TargetSelection {
+            bins: vec!["other".to_string()],
+            lib: false,
+        };
+
+        let err = determine_targets(&pkg, &selection).unwrap_err();
+
+        assert!(err.to_string().contains("\"other\""));
+    }
+
+    fn utils_plugin_and_other() -> Metadata {
+        let wapm = json!({ "namespace": "wasmer", "abi": "wasi" });
+
+        workspace(vec![
+            package_json(
+                "my-utils",
+                vec![target("my_utils", "cdylib")],
+                wapm.clone(),
+                Vec::new(),
+            ),
+            package_json(
+                "my-plugin",
+                vec![target("my_plugin", "cdylib")],
+                wapm.clone(),
+                vec!["my-utils"],
+            ),
+            package_json("other", vec![target("other", "cdylib")], wapm, Vec::new()),
+        ])
+    }
+
+    fn summarize(outcomes: &[(&Package, Outcome)]) -> Vec<String> {
+        outcomes
+            .iter()
+            .map(|(pkg, outcome)| format!("{}: {}", pkg.name, outcome))
+            .collect()
+    }
+
+    #[test]
+    fn save_and_load_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wapm").join(PROGRESS_FILE);
+        let metadata = utils_plugin_and_other();
+        let utils = &metadata.packages[0];
+        let version = utils.version.clone();
+
+        let mut progress = Progress::load(&path).unwrap();
+        assert_eq!(progress, Progress::default());
+
+        progress.record(utils, &version);
+        progress.save(&path).unwrap();
+        let loaded = Progress::load(&path).unwrap();
+
+        assert_eq!(loaded, progress);
+        assert!(loaded.contains(utils, &version));
+        assert!(!loaded.contains(utils, &"2.0.0".parse().unwrap()));
+        assert!(!loaded.contains(&metadata.packages[1], &version));
+    }
+
+    #[test]
+    fn skip_packages_when_a_dependency_fails() {
+        let metadata = utils_plugin_and_other();
+        let packages: Vec<&Package> = metadata.packages.iter().collect();
+        let mut attempted = Vec::new();
+
+        let outcomes = publish_packages(
+            &packages,
+            &BuildOptions::default(),
+            true,
+            &mut Progress::default(),
+            None,
+            |pkg| {
+                attempted.push(pkg.name.clone());
+                if pkg.name == "my-utils" {
+                    anyhow::bail!("Upload failed");
+                }
+                Ok(Outcome::Published)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(attempted, vec!["my-utils", "other"]);
+        assert_eq!(
+            summarize(&outcomes),
+            vec![
+                "my-utils: failed (Upload failed)",
+                "my-plugin: skipped (my-utils wasn't published)",
+                "other: published",
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_at_the_first_failure_without_keep_going() {
+        let metadata = utils_plugin_and_other();
+        let packages: Vec<&Package> = metadata.packages.iter().collect();
+        let mut attempted = Vec::new();
+
+        let result = publish_packages(
+            &packages,
+            &BuildOptions::default(),
+            false,
+            &mut Progress::default(),
+            None,
+            |pkg| {
+                attempted.push(pkg.name.clone());
+                anyhow::bail!("Upload failed");
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(attempted, vec!["my-utils"]);
+    }
+
+    #[test]
+    fn resume_skips_packages_which_were_already_published() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROGRESS_FILE);
+        std::fs::write(&path, "my-utils@1.2.3\nother@1.0.0\n").unwrap();
+        let metadata = utils_plugin_and_other();
+        let packages: Vec<&Package> = metadata.packages.iter().collect();
+        let mut progress = Progress::load(&path).unwrap();
+        let mut attempted = Vec::new();
+
+        let outcomes = publish_packages(
+            &packages,
+            &BuildOptions::default(),
+            false,
+            &mut progress,
+            Some(&path),
+            |pkg| {
+                attempted.push(pkg.name.clone());
+                Ok(Outcome::Published)
+            },
+        )
+        .unwrap();
+
+        // "other" was published with a different version last time
+        assert_eq!(attempted, vec!["my-plugin", "other"]);
+        assert_eq!(
+            summarize(&outcomes),
+            vec![
+                "my-utils: already published",
+                "my-plugin: published",
+                "other: published",
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "my-utils@1.2.3\nother@1.0.0\nmy-plugin@1.2.3\nother@1.2.3\n"
+        );
+    }
+}
@@ -0,0 +1,508 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Error};
+use cargo_metadata::semver::Version;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use wapm_toml::Manifest;
+
+/// The GraphQL endpoint for the main WAPM registry.
+pub(crate) const DEFAULT_REGISTRY: &str = "https://registry.wapm.io/graphql";
+
+/// A registry that packages can be published to.
+pub(crate) trait Registry {
+    /// Has this version of a package already been published?
+    fn version_exists(&self, package_name: &str, version: &Version) -> Result<bool, Error>;
+
+    /// Upload a package to the registry.
+    fn publish(&self, upload: &Upload) -> Result<(), Error>;
+}
+
+/// Everything needed to publish a package.
+#[derive(Debug, Clone)]
+pub(crate) struct Upload {
+    pub manifest: Manifest,
+    /// The contents of the package's README.
+    pub readme: Option<String>,
+    /// The contents of the package's license file.
+    pub license_file: Option<String>,
+    /// The package's `*.tar.gz` archive.
+    pub archive: Vec<u8>,
+}
+
+/// A [`Registry`] that is accessed using its GraphQL API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GraphQLRegistry {
+    endpoint: String,
+    token: Option<String>,
+}
+
+impl GraphQLRegistry {
+    pub(crate) fn new(endpoint: impl Into<String>) -> Self {
+        GraphQLRegistry {
+            endpoint: endpoint.into(),
+            token: None,
+        }
+    }
+
+    /// Authenticate requests using this token.
+    pub(crate) fn with_token(self, token: impl Into<Option<String>>) -> Self {
+        GraphQLRegistry {
+            token: token.into(),
+            ..self
+        }
+    }
+
+    #[tracing::instrument(skip(self, variables))]
+    fn query<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<T, Error> {
+        let body = json!({ "query": query, "variables": variables }).to_string();
+        self.execute("application/json", body.as_bytes())
+    }
+
+    fn execute<T: DeserializeOwned>(&self, content_type: &str, body: &[u8]) -> Result<T, Error> {
+        tracing::debug!(
+            endpoint = %self.endpoint,
+            bytes = body.len(),
+            "Sending a GraphQL request",
+        );
+
+        let mut request = ureq::post(&self.endpoint).set("Content-Type", content_type);
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+
+        let response = match request.send_bytes(body) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+
+                // GraphQL servers will often include a more useful error
+                // message in the body
+                if let Ok(response) = serde_json::from_str::<GraphQLResponse<T>>(&body) {
+                    if !response.errors.is_empty() {
+                        return response.into_result();
+                    }
+                }
+
+                anyhow::bail!(
+                    "The registry responded with a {} status code: {}",
+                    status,
+                    body.trim()
+                );
+            }
+            Err(e) => {
+                return Err(Error::from(e)
+                    .context(format!("Unable to send a request to \"{}\"", self.endpoint)));
+            }
+        };
+
+        let response: GraphQLResponse<T> = response
+            .into_json()
+            .context("Unable to deserialize the response")?;
+
+        response.into_result()
+    }
+}
+
+impl Registry for GraphQLRegistry {
+    fn version_exists(&self, package_name: &str, version: &Version) -> Result<bool, Error> {
+        const QUERY: &str = "query GetPackageVersion($name: String!, $version: String) {
+            getPackageVersion(name: $name, version: $version) { version }
+        }";
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Data {
+            get_package_version: Option<serde_json::Value>,
+        }
+
+        let data: Data = self.query(
+            QUERY,
+            json!({ "name": package_name, "version": version.to_string() }),
+        )?;
+
+        Ok(data.get_package_version.is_some())
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn publish(&self, upload: &Upload) -> Result<(), Error> {
+        const MUTATION: &str = "mutation PublishPackage(
+            $name: String!,
+            $version: String!,
+            $description: String!,
+            $manifest: String!,
+            $license: String,
+            $licenseFile: String,
+            $readme: String,
+            $fileName: String,
+            $repository: String,
+            $homepage: String
+        ) {
+            publishPackage(input: {
+                name: $name,
+                version: $version,
+                description: $description,
+                manifest: $manifest,
+                license: $license,
+                licenseFile: $licenseFile,
+                readme: $readme,
+                file: $fileName,
+                repository: $repository,
+                homepage: $homepage,
+                clientMutationId: \"\"
+            }) {
+                success
+            }
+        }";
+        const FILE_NAME: &str = "package.tar.gz";
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Data {
+            publish_package: Option<PublishPackage>,
+        }
+
+        #[derive(Deserialize)]
+        struct PublishPackage {
+            success: bool,
+        }
+
+        anyhow::ensure!(
+            self.token.is_some(),
+            "An API token is required to publish packages. Either pass one in with \"--token\" or log in with \"wapm login\""
+        );
+
+        let Upload {
+            manifest,
+            readme,
+            license_file,
+            archive,
+        } = upload;
+        let package = &manifest.package;
+        let manifest = toml::to_string(manifest).context("Unable to serialize the wapm.toml")?;
+
+        let variables = json!({
+            "name": package.name,
+            "version": package.version.to_string(),
+            "description": package.description,
+            "manifest": manifest,
+            "license": package.license,
+            "licenseFile": license_file,
+            "readme": readme,
+            "fileName": FILE_NAME,
+            "repository": package.repository,
+            "homepage": package.homepage,
+        });
+
+        tracing::info!(
+            name = package.name.as_str(),
+            version = %package.version,
+            bytes = archive.len(),
+            "Uploading to the registry",
+        );
+
+        let body = multipart_body(
+            &[
+                ("query", MUTATION),
+                ("operationName", "PublishPackage"),
+                ("variables", &variables.to_string()),
+            ],
+            (FILE_NAME, archive),
+        );
+        let content_type = format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY);
+
+        let data: Data = self.execute(&content_type, &body)?;
+
+        match data.publish_package {
+            Some(PublishPackage { success: true }) => Ok(()),
+            _ => anyhow::bail!("The registry didn't accept the package"),
+        }
+    }
+}
+
+const MULTIPART_BOUNDARY: &str = "------------------------cargo-wapm-d3b07384d113edec";
+
+/// Create a `multipart/form-data` body containing each of the GraphQL
+/// request's fields followed by the package archive, which is how the registry
+/// expects packages to be uploaded.
+fn multipart_body(fields: &[(&str, &str)], (file_name, file): (&str, &[u8])) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                MULTIPART_BOUNDARY, name, value
+            )
+            .as_bytes(),
+        );
+    }
+
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/gzip\r\n\r\n",
+            MULTIPART_BOUNDARY, file_name, file_name
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+
+    body
+}
+
+/// Try to find the API token saved by `wapm login`, as long as it was saved
+/// for the `registry` being published to.
+pub(crate) fn token_from_wapm_config(registry: &str) -> Option<String> {
+    let wasmer_dir = match std::env::var_os("WASMER_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+            PathBuf::from(home).join(".wasmer")
+        }
+    };
+    let path = wasmer_dir.join("wapm.toml");
+
+    let contents = std::fs::read_to_string(&path).ok()?;
+    let config: WapmConfig = toml::from_str(&contents).ok()?;
+
+    let token = config.token_for(registry);
+
+    if token.is_some() {
+        tracing::debug!(path = %path.display(), "Read the API token from the wapm config");
+    } else {
+        tracing::debug!(
+            path = %path.display(),
+            registry,
+            "The wapm config doesn't have a token for this registry",
+        );
+    }
+
+    token
+}
+
+/// The parts of the `wapm` CLI's config file we care about.
+#[derive(Debug, Deserialize)]
+struct WapmConfig {
+    registry: RegistryConfig,
+}
+
+impl WapmConfig {
+    fn token_for(&self, registry: &str) -> Option<String> {
+        let RegistryConfig { url, token, tokens } = &self.registry;
+
+        if let Some(saved) = tokens.iter().find(|t| same_registry(&t.registry, registry)) {
+            return Some(saved.token.clone());
+        }
+
+        // Older versions of the CLI only remember a single registry
+        match (url, token) {
+            (Some(url), Some(token)) if same_registry(url, registry) => Some(token.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryConfig {
+    url: Option<String>,
+    token: Option<String>,
+    #[serde(default)]
+    tokens: Vec<RegistryToken>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryToken {
+    registry: String,
+    token: String,
+}
+
+/// Compare two registry URLs, ignoring the GraphQL endpoint's path and any
+/// trailing slashes.
+fn same_registry(left: &str, right: &str) -> bool {
+    fn normalize(url: &str) -> &str {
+        url.trim_end_matches('/')
+            .trim_end_matches("/graphql")
+            .trim_end_matches('/')
+    }
+
+    normalize(left).eq_ignore_ascii_case(normalize(right))
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphQLError>,
+}
+
+impl<T> GraphQLResponse<T> {
+    fn into_result(self) -> Result<T, Error> {
+        if !self.errors.is_empty() {
+            let messages: Vec<_> = self.errors.into_iter().map(|e| e.message).collect();
+            anyhow::bail!("The registry responded with {}", messages.join("; "));
+        }
+
+        self.data
+            .context("The registry didn't send back any data or errors")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLError {
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::{Matcher, Server};
+
+    use super::*;
+
+    fn upload() -> Upload {
+        let manifest = Manifest::parse(
+            r#"
+            [package]
+            name = "wasmer/my-tool"
+            version = "1.2.3"
+            description = "A dummy package."
+            "#,
+        )
+        .unwrap();
+
+        Upload {
+            manifest,
+            readme: Some("# My Tool".to_string()),
+            license_file: None,
+            archive: b"pretend this is a tarball".to_vec(),
+        }
+    }
+
+    #[test]
+    fn check_whether_a_version_exists() {
+        let mut server = Server::new();
+        let exists = server
+            .mock("POST", "/graphql")
+            .match_body(Matcher::PartialJson(json!({
+                "variables": { "name": "wasmer/my-tool", "version": "1.2.3" },
+            })))
+            .with_body(r#"{ "data": { "getPackageVersion": { "version": "1.2.3" } } }"#)
+            .create();
+        let missing = server
+            .mock("POST", "/graphql")
+            .match_body(Matcher::PartialJson(json!({
+                "variables": { "name": "wasmer/my-tool", "version": "2.0.0" },
+            })))
+            .with_body(r#"{ "data": { "getPackageVersion": null } }"#)
+            .create();
+        let registry = GraphQLRegistry::new(format!("{}/graphql", server.url()));
+
+        assert!(registry
+            .version_exists("wasmer/my-tool", &Version::new(1, 2, 3))
+            .unwrap());
+        assert!(!registry
+            .version_exists("wasmer/my-tool", &Version::new(2, 0, 0))
+            .unwrap());
+        exists.assert();
+        missing.assert();
+    }
+
+    #[test]
+    fn publish_a_package() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("POST", "/graphql")
+            .match_header("authorization", "Bearer my-token")
+            .match_header(
+                "content-type",
+                Matcher::Regex("^multipart/form-data; boundary=".to_string()),
+            )
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex("mutation PublishPackage".to_string()),
+                Matcher::Regex(r##""readme":"# My Tool""##.to_string()),
+                Matcher::Regex("pretend this is a tarball".to_string()),
+            ]))
+            .with_body(r#"{ "data": { "publishPackage": { "success": true } } }"#)
+            .create();
+        let registry = GraphQLRegistry::new(format!("{}/graphql", server.url()))
+            .with_token("my-token".to_string());
+
+        registry.publish(&upload()).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn surface_errors_from_the_registry() {
+        let mut server = Server::new();
+        let _mock = server
+            .mock("POST", "/graphql")
+            .with_status(400)
+            .with_body(r#"{ "errors": [{ "message": "Version 1.2.3 already exists" }] }"#)
+            .create();
+        let registry = GraphQLRegistry::new(format!("{}/graphql", server.url()))
+            .with_token("my-token".to_string());
+
+        let err = registry.publish(&upload()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "The registry responded with Version 1.2.3 already exists"
+        );
+    }
+
+    #[test]
+    fn publishing_requires_a_token() {
+        let registry = GraphQLRegistry::new("http://localhost:1/graphql");
+
+        assert!(registry.publish(&upload()).is_err());
+    }
+
+    #[test]
+    fn errors_take_precedence_over_data() {
+        let response: GraphQLResponse<serde_json::Value> = serde_json::from_value(json!({
+            "data": { "getPackageVersion": null },
+            "errors": [
+                { "message": "First" },
+                { "message": "Second" },
+            ],
+        }))
+        .unwrap();
+
+        let err = response.into_result().unwrap_err();
+
+        assert_eq!(err.to_string(), "The registry responded with First; Second");
+    }
+
+    #[test]
+    fn only_use_saved_tokens_for_the_same_registry() {
+        let config: WapmConfig = toml::from_str(
+            r#"
+            [registry]
+            url = "https://registry.wapm.io"
+            token = "legacy-token"
+
+            [[registry.tokens]]
+            registry = "https://registry.wapm.dev/graphql"
+            token = "dev-token"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.token_for(DEFAULT_REGISTRY).as_deref(),
+            Some("legacy-token")
+        );
+        assert_eq!(
+            config
+                .token_for("https://registry.wapm.dev/graphql/")
+                .as_deref(),
+            Some("dev-token")
+        );
+        assert_eq!(config.token_for("https://example.com/graphql"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use wasm_encoder::RawSection;
+use wasmparser::{Encoding, Parser, Payload};
+
+use crate::{Strip, StripPreset};
+
+impl Strip {
+    /// The names of the custom sections this setting removes.
+    fn removed_sections(&self) -> Vec<&str> {
+        match self {
+            Strip::Preset(StripPreset::Debuginfo) => vec![".debug_*"],
+            Strip::Preset(StripPreset::Symbols) => vec![".debug_*", "name"],
+            Strip::Preset(StripPreset::All) => vec!["*"],
+            Strip::Sections(sections) => sections.remove.iter().map(String::as_str).collect(),
+        }
+    }
+
+    fn kept_sections(&self) -> Vec<&str> {
+        match self {
+            Strip::Preset(_) => Vec::new(),
+            Strip::Sections(sections) => sections.keep.iter().map(String::as_str).collect(),
+        }
+    }
+
+    fn should_remove(&self, section: &str) -> bool {
+        let matches = |pattern: &&str| match pattern.strip_suffix('*') {
+            Some(prefix) => section.starts_with(prefix),
+            None => section == *pattern,
+        };
+
+        self.removed_sections().iter().any(matches) && !self.kept_sections().iter().any(matches)
+    }
+}
+
+/// Copy a WebAssembly module to `dest`, removing any custom sections that
+/// `strip` says shouldn't be packaged.
+#[tracing::instrument(skip_all)]
+pub(crate) fn strip_custom_sections(src: &Path, dest: &Path, strip: &Strip) -> Result<(), Error> {
+    let wasm =
+        std::fs::read(src).with_context(|| format!("Unable to read \"{}\"", src.display()))?;
+
+    let stripped = strip_module(&wasm, strip)
+        .with_context(|| format!("Unable to strip \"{}\"", src.display()))?;
+
+    tracing::debug!(
+        from = %src.display(),
+        to = %dest.display(),
+        before = wasm.len(),
+        after = stripped.len(),
+        "Stripped custom sections",
+    );
+
+    std::fs::write(dest, stripped)
+        .with_context(|| format!("Unable to write to \"{}\"", dest.display()))
+}
+
+fn strip_module(wasm: &[u8], strip: &Strip) -> Result<Vec<u8>, Error> {
+    let mut module = wasm_encoder::Module::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+
+        match &payload {
+            Payload::Version { encoding, .. } => anyhow::ensure!(
+                *encoding == Encoding::Module,
+                "Only WebAssembly modules can be stripped, not components"
+            ),
+            Payload::CustomSection(section) if strip.should_remove(section.name()) => {
+                tracing::trace!(name = section.name(), "Removing a custom section");
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some((id, range)) = payload.as_section() {
+            module.section(&RawSection {
+                id,
+                data: &wasm[range],
+            });
+        }
+    }
+
+    Ok(module.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_encoder::{CustomSection, Module, TypeSection};
+
+    use super::*;
+    use crate::StripSections;
+
+    fn module_with_sections(names: &[&str]) -> Vec<u8> {
+        let mut module = Module::new();
+        let mut types = TypeSection::new();
+        types.function([], []);
+        module.section(&types);
+
+        for name in names {
+            module.section(&CustomSection {
+                name: (*name).into(),
+                data: b"data".as_slice().into(),
+            });
+        }
+
+        module.finish()
+    }
+
+    fn custom_sections(wasm: &[u8]) -> Vec<String> {
+        Parser::new(0)
+            .parse_all(wasm)
+            .filter_map(|payload| match payload.unwrap() {
+                Payload::CustomSection(s) => Some(s.name().to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strip_with_presets_and_explicit_sections() {
+        let wasm = module_with_sections(&["name", "producers", ".debug_info", ".debug_line"]);
+        let inputs = [
+            (
+                Strip::Preset(StripPreset::Debuginfo),
+                vec!["name", "producers"],
+            ),
+            (Strip::Preset(StripPreset::Symbols), vec!["producers"]),
+            (Strip::Preset(StripPreset::All), vec![]),
+            (
+                Strip::Sections(StripSections {
+                    remove: vec!["*".to_string()],
+                    keep: vec!["name".to_string(), ".debug_line".to_string()],
+                }),
+                vec!["name", ".debug_line"],
+            ),
+        ];
+
+        for (strip, should_be) in inputs {
+            let stripped = strip_module(&wasm, &strip).unwrap();
+
+            assert_eq!(custom_sections(&stripped), should_be, "{:?}", strip);
+            wasmparser::validate(&stripped).unwrap();
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use cargo_metadata::Target;
+use wai_parser::abi::{AbiVariant, WasmType};
+use wapm_toml::{Abi, Bindings, Manifest};
+use wasmparser::{CompositeType, Encoding, ExternalKind, FuncType, Parser, Payload, ValType};
+
+/// The import namespaces provided by WASI.
+const WASI_MODULES: &[&str] = &["wasi_snapshot_preview1", "wasi_unstable"];
+
+/// The imports and exports of a compiled WebAssembly module.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Interface {
+    /// The `(module, name)` pairs this module imports.
+    imports: Vec<(String, String)>,
+    /// The names and signatures of all exported functions.
+    exported_functions: Vec<(String, FuncType)>,
+}
+
+impl Interface {
+    fn parse(wasm: &[u8]) -> Result<Self, Error> {
+        let mut interface = Interface::default();
+        // The type section may contain non-function types, so we keep
+        // `None` placeholders to make sure indices still line up
+        let mut types: Vec<Option<FuncType>> = Vec::new();
+        // The type index for every function, starting with imported ones
+        let mut functions: Vec<u32> = Vec::new();
+
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::Version { encoding, .. } => anyhow::ensure!(
+                    encoding == Encoding::Module,
+                    "Expected a WebAssembly module, not a component"
+                ),
+                Payload::TypeSection(section) => {
+                    for rec_group in section {
+                        types.extend(rec_group?.into_types().map(|ty| match ty.composite_type {
+                            CompositeType::Func(f) => Some(f),
+                            _ => None,
+                        }));
+                    }
+                }
+                Payload::ImportSection(section) => {
+                    for import in section {
+                        let import = import?;
+                        if let wasmparser::TypeRef::Func(ty) = import.ty {
+                            functions.push(ty);
+                        }
+                        interface
+                            .imports
+                            .push((import.module.to_string(), import.name.to_string()));
+                    }
+                }
+                Payload::FunctionSection(section) => {
+                    for ty in section {
+                        functions.push(ty?);
+                    }
+                }
+                Payload::ExportSection(section) => {
+                    for export in section {
+                        let export = export?;
+                        if export.kind != ExternalKind::Func {
+                            continue;
+                        }
+
+                        let signature = functions
+                            .get(export.index as usize)
+                            .and_then(|&ty| types.get(ty as usize).cloned().flatten())
+                            .with_context(|| {
+                                format!("Unable to find the signature for \"{}\"", export.name)
+                            })?;
+                        interface
+                            .exported_functions
+                            .push((export.name.to_string(), signature));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(interface)
+    }
+
+    fn wasi_imports(&self) -> impl Iterator<Item = &(String, String)> {
+        self.imports
+            .iter()
+            .filter(|(module, _)| WASI_MODULES.contains(&module.as_str()))
+    }
+
+    fn exported_function(&self, name: &str) -> Option<&FuncType> {
+        self.exported_functions
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, signature)| signature)
+    }
+}
+
+/// Make sure each compiled module's imports and exports are consistent with
+/// the `abi` it will be published with.
+#[tracing::instrument(skip_all)]
+pub(crate) fn validate_modules(
+    manifest: &Manifest,
+    targets: &[&Target],
+    wasm_paths: &[PathBuf],
+    base_dir: &Path,
+) -> Result<(), Error> {
+    let modules = manifest.module.as_deref().unwrap_or_default();
+    let commands = manifest.command.as_deref().unwrap_or_default();
+
+    for ((module, target), path) in modules.iter().zip(targets).zip(wasm_paths) {
+        let command = commands
+            .iter()
+            .find(|cmd| cmd.get_module() == module.name)
+            .map(|cmd| cmd.get_name());
+
+        let interface = validate_module(
+            path,
+            &module.name,
+            module.abi,
+            crate::publish::is_binary(target),
+            command.as_deref(),
+        )?;
+
+        if let (Some(interface), Some(bindings)) = (interface, &module.bindings) {
+            check_bindings(&interface, &module.name, bindings, base_dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_module(
+    path: &Path,
+    name: &str,
+    abi: Abi,
+    is_binary: bool,
+    command: Option<&str>,
+) -> Result<Option<Interface>, Error> {
+    let wasm =
+        std::fs::read(path).with_context(|| format!("Unable to read \"{}\"", path.display()))?;
+
+    if Parser::is_component(&wasm) {
+        // Components (e.g. from wasm32-wasip2) use the component model's
+        // imports and exports instead of the core ABI
+        tracing::debug!(module = name, "Skipping validation for a component");
+        return Ok(None);
+    }
+    let interface = Interface::parse(&wasm)
+        .with_context(|| format!("Unable to parse \"{}\"", path.display()))?;
+
+    tracing::debug!(
+        module = name,
+        imports = interface.imports.len(),
+        exports = interface.exported_functions.len(),
+        "Validating the compiled module",
+    );
+
+    check_interface(&interface, name, abi, is_binary, command)?;
+
+    Ok(Some(interface))
+}
+
+fn check_interface(
+    interface: &Interface,
+    name: &str,
+    abi: Abi,
+    is_binary: bool,
+    command: Option<&str>,
+) -> Result<(), Error> {
+    match abi {
+        Abi::Wasi => {
+            let uses_wasi = interface.wasi_imports().next().is_some();
+
+            if !uses_wasi && is_binary {
+                anyhow::bail!(
+                    "The \"{}\" module is published with abi = \"wasi\", but it doesn't import anything from \"{}\". Was it compiled for the right target?",
+                    name,
+                    WASI_MODULES[0],
+                );
+            } else if !uses_wasi {
+                tracing::warn!(
+                    module = name,
+                    "The module is published with abi = \"wasi\", but it doesn't use WASI. Consider using abi = \"none\" instead",
+                );
+            }
+
+            if let Some(command) = command {
+                anyhow::ensure!(
+                    interface.exported_function("_start").is_some(),
+                    "The \"{}\" command runs the \"{}\" module, but it doesn't export a \"_start\" function",
+                    command,
+                    name,
+                );
+            }
+        }
+        Abi::None | Abi::WASM4 => {
+            if let Some((module, function)) = interface.wasi_imports().next() {
+                anyhow::bail!(
+                    "The \"{}\" module is published with abi = \"{}\", but it imports \"{}\" from \"{}\". Either set abi = \"wasi\" or remove the WASI dependency",
+                    name,
+                    if abi == Abi::WASM4 { "wasm4" } else { "none" },
+                    function,
+                    module,
+                );
+            }
+        }
+        Abi::Emscripten => {}
+    }
+
+    Ok(())
+}
+
+/// Make sure a module exports every function declared by its bindings, and
+/// that their signatures match the canonical ABI.
+fn check_bindings(
+    interface: &Interface,
+    name: &str,
+    bindings: &Bindings,
+    base_dir: &Path,
+) -> Result<(), Error> {
+    // Note: bindings for wit-bindgen 0.1 use the same syntax as WAI
+    let exports = match bindings {
+        Bindings::Wai(wai) => match &wai.exports {
+            Some(exports) => exports,
+            None => return Ok(()),
+        },
+        Bindings::Wit(wit) => &wit.wit_exports,
+    };
+    let path = base_dir.join(exports);
+
+    let exports = wai_parser::Interface::parse_file(&path)
+        .with_context(|| format!("Unable to parse \"{}\"", path.display()))?;
+
+    let problems = binding_problems(interface, &exports);
+
+    anyhow::ensure!(
+        problems.is_empty(),
+        "The \"{}\" module doesn't match \"{}\":\n{}",
+        name,
+        path.display(),
+        problems.join("\n"),
+    );
+
+    Ok(())
+}
+
+fn binding_problems(interface: &Interface, exports: &wai_parser::Interface) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for function in &exports.functions {
+        let expected = exports.wasm_signature(AbiVariant::GuestExport, function);
+        let expected = FuncType::new(
+            expected.params.iter().copied().map(val_type),
+            expected.results.iter().copied().map(val_type),
+        );
+
+        match interface.exported_function(&function.name) {
+            Some(actual) if *actual == expected => {}
+            Some(actual) => problems.push(format!(
+                "  - \"{}\" should have the signature {}, but it is {}",
+                function.name,
+                signature(&expected),
+                signature(actual),
+            )),
+            None => problems.push(format!("  - \"{}\" isn't exported", function.name)),
+        }
+    }
+
+    problems
+}
+
+fn val_type(ty: WasmType) -> ValType {
+    match ty {
+        WasmType::I32 => ValType::I32,
+        WasmType::I64 => ValType::I64,
+        WasmType::F32 => ValType::F32,
+        WasmType::F64 => ValType::F64,
+    }
+}
+
+fn signature(ty: &FuncType) -> String {
+    let join = |types: &[ValType]| {
+        types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!("({}) -> ({})", join(ty.params()), join(ty.results()))
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_encoder::{
+        CodeSection, EntityType, ExportKind, ExportSection, Function, FunctionSection,
+        ImportSection, Instruction, Module, TypeSection,
+    };
+
+    use super::*;
+
+    fn module(imports: &[(&str, &str)], exports: &[&str]) -> Vec<u8> {
+        let exports: Vec<_> = exports
+            .iter()
+            .map(|name| (*name, &[][..], &[][..]))
+            .collect();
+        module_with_signatures(imports, &exports)
+    }
+
+    fn module_with_signatures(
+        imports: &[(&str, &str)],
+        exports: &[(&str, &[ValType], &[ValType])],
+    ) -> Vec<u8> {
+        let mut module = Module::new();
+
+        let mut types = TypeSection::new();
+        types.function([], []);
+        for (_, params, results) in exports {
+            types.function(
+                params.iter().map(|&t| encoder_type(t)),
+                results.iter().map(|&t| encoder_type(t)),
+            );
+        }
+        module.section(&types);
+
+        let mut import_section = ImportSection::new();
+        for (module, name) in imports {
+            import_section.import(module, name, EntityType::Function(0));
+        }
+        module.section(&import_section);
+
+        let mut functions = FunctionSection::new();
+        let mut export_section = ExportSection::new();
+        let mut code = CodeSection::new();
+        for (i, (name, _, _)) in exports.iter().enumerate() {
+            functions.function(i as u32 + 1);
+            export_section.export(name, ExportKind::Func, (imports.len() + i) as u32);
+            let mut body = Function::new([]);
+            body.instruction(&Instruction::Unreachable);
+            body.instruction(&Instruction::End);
+            code.function(&body);
+        }
+        module.section(&functions);
+        module.section(&export_section);
+        module.section(&code);
+
+        module.finish()
+    }
+
+    fn encoder_type(ty: ValType) -> wasm_encoder::ValType {
+        match ty {
+            ValType::I32 => wasm_encoder::ValType::I32,
+            ValType::I64 => wasm_encoder::ValType::I64,
+            ValType::F32 => wasm_encoder::ValType::F32,
+            ValType::F64 => wasm_encoder::ValType::F64,
+            other => unreachable!("Unsupported type: {}", other),
+        }
+    }
+
+    fn check(wasm: &[u8], abi: Abi, is_binary: bool, command: Option<&str>) -> Result<(), Error> {
+        let interface = Interface::parse(wasm).unwrap();
+        check_interface(&interface, "my-module", abi, is_binary, command)
+    }
+
+    #[test]
+    fn parse_imports_and_exports() {
+        let wasm = module(&[("wasi_snapshot_preview1", "fd_write")], &["_start"]);
+
+        let interface = Interface::parse(&wasm).unwrap();
+
+        assert_eq!(
+            interface,
+            Interface {
+                imports: vec![("wasi_snapshot_preview1".to_string(), "fd_write".to_string())],
+                exported_functions: vec![("_start".to_string(), FuncType::new([], []))],
+            }
+        );
+    }
+
+    #[test]
+    fn wasi_commands_need_wasi_imports_and_a_start_function() {
+        let wasi_command = module(&[("wasi_snapshot_preview1", "fd_write")], &["_start"]);
+        let no_start = module(&[("wasi_snapshot_preview1", "fd_write")], &["main"]);
+        let no_imports = module(&[], &["_start"]);
+
+        check(&wasi_command, Abi::Wasi, true, Some("my-command")).unwrap();
+        check(&no_start, Abi::Wasi, true, None).unwrap();
+
+        let err = check(&no_start, Abi::Wasi, true, Some("my-command")).unwrap_err();
+        assert!(err.to_string().contains("\"_start\""));
+        let err = check(&no_imports, Abi::Wasi, true, Some("my-command")).unwrap_err();
+        assert!(err.to_string().contains("wasi_snapshot_preview1"));
+        // Libraries don't necessarily need to use WASI
+        check(&no_imports, Abi::Wasi, false, None).unwrap();
+    }
+
+    #[test]
+    fn wasi_imports_are_rejected_for_other_abis() {
+        let wasi = module(&[("wasi_unstable", "fd_write")], &["main"]);
+        let plain = module(&[("env", "log")], &["main"]);
+
+        check(&plain, Abi::None, true, Some("my-command")).unwrap();
+        check(&plain, Abi::WASM4, false, None).unwrap();
+
+        let err = check(&wasi, Abi::None, true, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The \"my-module\" module is published with abi = \"none\", but it imports \"fd_write\" from \"wasi_unstable\". Either set abi = \"wasi\" or remove the WASI dependency"
+        );
+    }
+
+    #[test]
+    fn exports_must_match_the_bindings() {
+        let exports = wai_parser::Interface::parse(
+            "exports",
+            "add: func(a: u32, b: u32) -> u32\ngreet: func(name: string) -> string\nreset: func()",
+        )
+        .unwrap();
+        let i32 = ValType::I32;
+        let matching = module_with_signatures(
+            &[],
+            &[
+                ("add", &[i32, i32], &[i32]),
+                ("greet", &[i32, i32], &[i32]),
+                ("reset", &[], &[]),
+            ],
+        );
+        let mismatched = module_with_signatures(&[], &[("add", &[ValType::I64, i32], &[i32])]);
+
+        let problems = binding_problems(&Interface::parse(&matching).unwrap(), &exports);
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        let problems = binding_problems(&Interface::parse(&mismatched).unwrap(), &exports);
+        assert_eq!(
+            problems,
+            vec![
+                "  - \"add\" should have the signature (i32, i32) -> (i32), but it is (i64, i32) -> (i32)",
+                "  - \"greet\" isn't exported",
+                "  - \"reset\" isn't exported",
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Error;
+use cargo_metadata::{DependencyKind, Package};
+
+/// Get the packages from `candidates` that `pkg` depends on.
+///
+/// Dev-dependencies are ignored because they aren't needed when publishing
+/// and would otherwise be able to introduce cycles.
+pub(crate) fn local_dependencies<'a>(
+    pkg: &Package,
+    candidates: &[&'a Package],
+) -> Vec<&'a Package> {
+    candidates
+        .iter()
+        .copied()
+        .filter(|candidate| {
+            pkg.dependencies.iter().any(|dep| {
+                dep.kind != DependencyKind::Development
+                    && dep.name == candidate.name
+                    && dep.path.as_deref() == candidate.manifest_path.parent()
+            })
+        })
+        .collect()
+}
+
+/// Sort `packages` so each package comes after the packages it depends on,
+/// otherwise keeping their original order.
+#[tracing::instrument(skip_all)]
+pub(crate) fn publish_order<'a>(packages: &[&'a Package]) -> Result<Vec<&'a Package>, Error> {
+    let mut remaining = packages.to_vec();
+    let mut ordered: Vec<&Package> = Vec::with_capacity(packages.len());
+
+    while !remaining.is_empty() {
+        let next = remaining.iter().position(|pkg| {
+            local_dependencies(pkg, packages)
+                .iter()
+                .all(|dep| ordered.iter().any(|p| p.id == dep.id))
+        });
+
+        match next {
+            Some(index) => {
+                let pkg = remaining.remove(index);
+                tracing::debug!(pkg = pkg.name.as_str(), "Adding to the publish order");
+                ordered.push(pkg);
+            }
+            None => anyhow::bail!(
+                "Unable to determine a publish order because there is a dependency cycle between {}",
+                remaining
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::fixtures::{dependency, package_json};
+
+    fn package(name: &str, dependencies: &[(&str, &str)]) -> Package {
+        let mut pkg = package_json(name, Vec::new(), json!(null), Vec::new());
+        pkg["dependencies"] = dependencies
+            .iter()
+            .map(|(dep, kind)| dependency(dep, kind))
+            .collect();
+        serde_json::from_value(pkg).unwrap()
+    }
+
+    fn names<'a>(packages: &[&'a Package]) -> Vec<&'a str> {
+        packages.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn dependencies_are_published_first() {
+        let app = package("app", &[("plugin", "normal"), ("utils", "build")]);
+        let plugin = package("plugin", &[("utils", "normal")]);
+        let utils = package("utils", &[]);
+        let other = package("other", &[]);
+
+        let got = publish_order(&[&app, &plugin, &other, &utils]).unwrap();
+
+        assert_eq!(names(&got), vec!["other", "utils", "plugin", "app"]);
+    }
+
+    #[test]
+    fn dev_dependencies_are_ignored() {
+        let first = package("first", &[("second", "dev")]);
+        let second = package("second", &[("first", "normal")]);
+
+        let got = publish_order(&[&first, &second]).unwrap();
+
+        assert_eq!(names(&got), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn cycles_are_an_error() {
+        let first = package("first", &[("second", "normal")]);
+        let second = package("second", &[("first", "build")]);
+
+        assert!(publish_order(&[&first, &second]).is_err());
+    }
+}